@@ -1,46 +1,425 @@
-use anyhow::{Result, bail};
-use chrono::{Duration, Utc};
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Duration, DurationRound, NaiveDate, NaiveTime, Utc};
 use colored::Colorize;
 use futures::{StreamExt, stream};
 use reqwest::Client;
+use serde::Serialize;
 use std::collections::HashMap;
-
-use crate::config::Config;
-use crate::fetch::fetch_feed;
-use crate::state::{Feed, Item, State};
-use crate::{Cli, Cmd};
-
-pub async fn run_command(cli: Cli, cfg: &Config, state: &mut State) -> Result<()> {
-    let limit = cli.limit.unwrap_or(cfg.default_limit);
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+
+use crate::config::{Config, Webhook};
+use crate::discover::{FeedCandidate, discover_feeds};
+use crate::fetch::{FetchOutcome, fetch_feed, parse_feed};
+use crate::filters::{self, FilterAction, Verdict};
+use crate::hooks::run_hook;
+use crate::lock::LockMode;
+use crate::opener::open_link;
+use crate::opml;
+use crate::render::render_html;
+use crate::search::{self, SearchQuery};
+use crate::state::{self, Delivery, Feed, FeedSelector, Item, SCHEMA_VERSION, normalize_tag};
+use crate::storage::{self, ItemQuery, Storage};
+use crate::template::Template;
+use crate::webhooks::{self, PostOutcome};
+use crate::{Cli, Cmd, OutputFormat, StateCmd};
+
+/// Run one command against the store.
+///
+/// Returns what is left to do once the state is saved and unlocked (see
+/// `run_follow_up`).
+pub async fn run_command(cli: Cli, cfg: &Config, store: &mut dyn Storage) -> Result<FollowUp> {
+    let (view, selector) = view_options(&cli, cfg)?;
+    let mut new_items = None;
+    let mut to_open = Vec::new();
 
     match cli.command {
-        Some(Cmd::Sub { url, alias }) => {
-            cmd_sub(state, &url, alias)?;
-        }
         Some(Cmd::Unsub { id_or_url }) => {
-            cmd_unsub(state, &id_or_url)?;
+            cmd_unsub(store, &id_or_url)?;
         }
         Some(Cmd::List) => {
-            cmd_list(state)?;
-        }
-        Some(Cmd::Feed { id_or_url }) => {
-            cmd_show_feed(state, cfg, &id_or_url, limit).await?;
+            cmd_list(store, &selector, view.format)?;
         }
+        Some(Cmd::Feed { id_or_url }) => match id_or_url {
+            Some(key) => new_items = Some(cmd_show_feed(store, cfg, &key, &selector, &view).await?),
+            None if !selector.is_empty() => {
+                new_items = Some(cmd_show_all(store, cfg, &selector, &view).await?)
+            }
+            None => bail!("Specify a feed, or select feeds with --tag/--group"),
+        },
         Some(Cmd::Refresh { ids_or_urls }) => {
-            cmd_refresh(state, cfg, &ids_or_urls).await?;
+            new_items = Some(cmd_refresh(store, cfg, &ids_or_urls, &selector).await?);
         }
         Some(Cmd::Rename { key, alias }) => {
-            cmd_rename(state, &key, &alias)?;
+            cmd_rename(store, &key, &alias)?;
+        }
+        Some(Cmd::Tag { key, tags }) => {
+            cmd_tag(store, &key, &tags)?;
+        }
+        Some(Cmd::Untag { key, tags }) => {
+            cmd_untag(store, &key, &tags)?;
+        }
+        Some(Cmd::Group { key, group }) => {
+            cmd_group(store, &key, group.as_deref())?;
+        }
+        Some(Cmd::Set { key, settings }) => {
+            cmd_set(store, &key, &settings)?;
+        }
+        Some(Cmd::Read { items }) => {
+            cmd_read(store, &items)?;
+        }
+        Some(Cmd::Open { items, feed }) => {
+            to_open = cmd_open(store, cfg, &items, feed.as_deref(), &selector, &view)?;
+        }
+        Some(Cmd::Search { query }) => {
+            cmd_search(store, cfg, &query.join(" "), &selector, &view)?;
+        }
+        Some(Cmd::Show { item, width }) => {
+            cmd_show(store, &item, width)?;
+        }
+        Some(Cmd::MarkRead { feed, all: _ }) => {
+            cmd_mark_read(store, feed.as_deref(), &selector)?;
+        }
+        Some(Cmd::Import { file }) => {
+            cmd_import(store, &file)?;
         }
+        Some(Cmd::Export { output }) => {
+            cmd_export(store, output.as_deref())?;
+        }
+        Some(Cmd::Deliveries) => {
+            cmd_deliveries(store, &view)?;
+        }
+        Some(Cmd::Sub { .. }) | Some(Cmd::Watch) => {
+            unreachable!("sub and watch open the store themselves")
+        }
+        Some(Cmd::State { .. }) => unreachable!("state commands run before the store is opened"),
         None => {
-            // default: show recent items across all feeds
-            cmd_show_all(state, cfg, limit).await?;
+            // default: show recent items across all (selected) feeds
+            new_items = Some(cmd_show_all(store, cfg, &selector, &view).await?);
+        }
+    }
+
+    // Commands that refresh also retry any webhook deliveries left pending
+    let follow_up = match new_items {
+        Some(new_items) => after_refresh(store, cfg, new_items)?,
+        None => FollowUp::default(),
+    };
+    Ok(FollowUp {
+        to_open,
+        ..follow_up
+    })
+}
+
+/// What a command leaves to do once the state is saved and unlocked:
+/// nothing slow, or that might run rsso itself, happens under the lock
+#[derive(Default)]
+pub struct FollowUp {
+    /// Items seen for the first time, for `on_new_item` hooks
+    pub new_items: Vec<Item>,
+    /// Webhook deliveries this run has taken on
+    pub deliveries: Vec<PendingDelivery>,
+    /// Items to open in the browser and mark read
+    pub to_open: Vec<Item>,
+}
+
+/// A delivery taken on by this run, with the webhook and body to send
+pub struct PendingDelivery {
+    delivery: Delivery,
+    hook: Webhook,
+    body: String,
+}
+
+/// Follow up a refresh that found `new_items`: log their webhook
+/// deliveries, and take on those pending
+fn after_refresh(store: &mut dyn Storage, cfg: &Config, new_items: Vec<Item>) -> Result<FollowUp> {
+    let deliveries = queue_deliveries(store, cfg, &new_items, Utc::now())?;
+    Ok(FollowUp {
+        new_items,
+        deliveries,
+        to_open: Vec::new(),
+    })
+}
+
+/// Open the items to open, run the hooks for new items, then make the
+/// webhook deliveries with up to `webhook_tries` requests each. Opened items
+/// are marked read and the deliveries recorded under a fresh lock.
+///
+/// Call this after the state is saved and the lock released; `feeds` are
+/// those the command saw.
+pub async fn run_follow_up(
+    cfg: &Config,
+    feeds: &[Feed],
+    follow_up: FollowUp,
+    webhook_tries: u32,
+) -> Result<()> {
+    let (opened, opening) = open_items(cfg, &follow_up.to_open);
+    run_new_item_hooks(cfg, feeds, &follow_up.new_items);
+    let results = if follow_up.deliveries.is_empty() {
+        Vec::new()
+    } else {
+        let client = build_http_client()?;
+        post_deliveries(&client, follow_up.deliveries, webhook_tries).await
+    };
+    if opened.is_empty() && results.is_empty() {
+        return opening;
+    }
+
+    let (_lock, mut store) = storage::lock_and_open(cfg, LockMode::Exclusive)?;
+    store.mark_read(&opened)?;
+    record_deliveries(store.as_mut(), results, Utc::now())?;
+    store.save()?;
+    opening
+}
+
+/// Run `rsso sub`.
+///
+/// Finding the feed can mean asking which of a site's feeds to subscribe to,
+/// so the state is only locked to check for an existing subscription first,
+/// and to add the new one once the feed is found and fetched.
+pub async fn run_sub(cli: Cli, cfg: &Config) -> Result<()> {
+    let Some(Cmd::Sub { url, alias, force }) = cli.command else {
+        unreachable!("run_sub is only called for sub");
+    };
+
+    // No need to go looking for a feed we already have
+    {
+        let (_lock, store) = storage::lock_and_open(cfg, LockMode::Shared)?;
+        check_not_subscribed(store.as_ref(), &url, alias.as_deref())?;
+    }
+
+    let client = build_http_client()?;
+    let found = find_feed(&client, &url, force).await?;
+
+    let feed = Feed {
+        id: derive_feed_id(&found.url, alias.as_deref()),
+        url: found.url.clone(),
+        alias,
+        title: None, // filled in by the first fetch below
+        added_at: Utc::now(),
+        last_fetched_at: None,
+        last_error: None,
+        group: None,
+        tags: Default::default(),
+        etag: None,
+        last_modified: None,
+        overrides: Default::default(),
+        hints: Default::default(),
+    };
+
+    // Discovery usually downloaded the feed already
+    let fetch_result = match &found.document {
+        Some(doc) => parse_feed(&feed, &doc.headers, &doc.body).map(FetchOutcome::Updated),
+        None => fetch_feed(&client, &feed).await,
+    };
+    if let Err(err) = &fetch_result {
+        if !force {
+            bail!(
+                "Not subscribing to {}: {} (use --force to subscribe anyway)",
+                feed.url,
+                err
+            );
+        }
+        eprintln!("Warning: {}; subscribing anyway", err);
+    }
+
+    let (_lock, mut store) = storage::lock_and_open(cfg, LockMode::Exclusive)?;
+    cmd_sub(store.as_mut(), cfg, feed, fetch_result)?;
+    store.save()
+}
+
+/// Run `rsso watch` until interrupted
+pub async fn run_watch(cli: Cli, cfg: &Config) -> Result<()> {
+    let (view, selector) = view_options(&cli, cfg)?;
+    cmd_watch(cfg, &selector, &view).await
+}
+
+/// Listing options and feed selection from the global flags
+fn view_options(cli: &Cli, cfg: &Config) -> Result<(ViewOptions, FeedSelector)> {
+    let template = match &cli.template {
+        Some(t) => Template::parse(t).context("Invalid --template")?,
+        None => cfg.item_template.clone(),
+    };
+    let now = Utc::now();
+    let since = cli
+        .since
+        .as_deref()
+        .map(|s| parse_when(s, false, now).context("Invalid --since"))
+        .transpose()?;
+    let until = match cli.until.as_deref() {
+        None => None,
+        Some(s) => match parse_when(s, true, now).context("Invalid --until")? {
+            When::At(t) => Some(t),
+            When::LastRun => bail!("--until doesn't take last-run; use it with --since"),
+        },
+    };
+
+    let view = ViewOptions {
+        limit: cli.limit.unwrap_or(cfg.default_limit),
+        requested_limit: cli.limit,
+        unread_only: cli.unread,
+        since,
+        until,
+        format: cli.format,
+        template,
+    };
+    let selector = FeedSelector {
+        tags: cli.tags.clone(),
+        group: cli.group.clone(),
+    };
+    Ok((view, selector))
+}
+
+/// Run a `rsso state` subcommand against the state file itself
+pub fn run_state_command(action: &StateCmd, cfg: &Config) -> Result<()> {
+    match action {
+        StateCmd::Migrate { dry_run } => cmd_state_migrate(cfg, *dry_run),
+    }
+}
+
+type FetchResult = Result<FetchOutcome>;
+
+/// How item listings should be filtered and printed
+struct ViewOptions {
+    limit: usize,
+    /// `-n`, if given; it beats a feed's own display limit
+    requested_limit: Option<usize>,
+    unread_only: bool,
+    since: Option<When>,
+    until: Option<DateTime<Utc>>,
+    format: OutputFormat,
+    template: Template,
+}
+
+impl ViewOptions {
+    /// How many items to show for a single feed
+    fn limit_for(&self, feed: &Feed) -> usize {
+        self.requested_limit
+            .or(feed.overrides.limit)
+            .unwrap_or(self.limit)
+    }
+
+    /// The query for a listing of these feeds, with `--unread`, `--since`
+    /// and `--until` applied
+    fn item_query(
+        &self,
+        store: &dyn Storage,
+        feed_ids: Vec<String>,
+        limit: Option<usize>,
+    ) -> Result<ItemQuery> {
+        let mut query = ItemQuery {
+            feed_ids: Some(feed_ids),
+            unread_only: self.unread_only,
+            until: self.until,
+            limit,
+            ..ItemQuery::default()
+        };
+        match self.since {
+            Some(When::At(t)) => query.since = Some(t),
+            Some(When::LastRun) => query.seen_after = store.last_viewed_at()?,
+            None => {}
         }
+        Ok(query)
+    }
+}
+
+/// A point in time given to `--since`/`--until`
+#[derive(Debug, Clone, Copy)]
+enum When {
+    At(DateTime<Utc>),
+    /// When items were last listed on a terminal; compared with when items
+    /// were first seen rather than their dates
+    LastRun,
+}
+
+/// Parse "last-run", a date (YYYY-MM-DD), an RFC 3339 time, or a span back
+/// from `now` like "30m", "12h", "2d" or "1w".
+///
+/// A date means the start of that day, or for `until`, the end of it.
+fn parse_when(s: &str, until: bool, now: DateTime<Utc>) -> Result<When> {
+    let s = s.trim();
+    if s == "last-run" {
+        return Ok(When::LastRun);
+    }
+
+    if let Ok(day) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let day = if until { day.succ_opt() } else { Some(day) };
+        let Some(day) = day else {
+            bail!("Invalid date '{}'", s);
+        };
+        return Ok(When::At(day.and_time(NaiveTime::MIN).and_utc()));
+    }
+
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(When::At(t.with_timezone(&Utc)));
+    }
+
+    if !s.starts_with(|c: char| c.is_ascii_digit()) {
+        bail!(
+            "Expected a date (2024-05-01), a span like 2d, or last-run; got '{}'",
+            s
+        );
+    }
+    match now.checked_sub_signed(parse_span(s)?) {
+        Some(t) => Ok(When::At(t)),
+        None => bail!("Invalid time span '{}': too far back", s),
+    }
+}
+
+/// Parse a span like "30m", "12h", "2d" or "1w"
+fn parse_span(s: &str) -> Result<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (count, unit) = s.split_at(split);
+    let count: i64 = match count.parse() {
+        Ok(n) => n,
+        Err(_) => bail!("Expected a span like 30m, 12h, 2d or 1w; got '{}'", s),
+    };
+    let span = match unit {
+        "m" => Duration::try_minutes(count),
+        "h" => Duration::try_hours(count),
+        "d" => Duration::try_days(count),
+        "w" => Duration::try_weeks(count),
+        _ => bail!("Unknown unit '{}' in '{}' (use m, h, d or w)", unit, s),
+    };
+    match span {
+        Some(span) => Ok(span),
+        None => bail!("Invalid time span '{}': too long", s),
     }
+}
 
+/// Remember that the default view was just listed, for `--since last-run`.
+///
+/// Only listings printed to a terminal count, so scripts and cron jobs don't
+/// move the mark, and only those that show everything new (see
+/// `shows_all_new`).
+fn record_view(store: &mut dyn Storage, view: &ViewOptions, selector: &FeedSelector) -> Result<()> {
+    if shows_all_new(view, selector)
+        && view.format == OutputFormat::Text
+        && io::stdout().is_terminal()
+    {
+        store.set_last_viewed_at(Utc::now())?;
+    }
     Ok(())
 }
 
+/// Whether a default-view listing leaves nothing new out: a listing
+/// narrowed to some feeds, or by date, mustn't hide the rest from the next
+/// `--since last-run`
+fn shows_all_new(view: &ViewOptions, selector: &FeedSelector) -> bool {
+    selector.is_empty() && view.until.is_none() && matches!(view.since, None | Some(When::LastRun))
+}
+
+/// JSON record for one item: every `Item` field plus its feed's label, and
+/// whether a `[[filters]]` rule highlights it
+#[derive(Serialize)]
+struct ItemRecord<'a> {
+    #[serde(flatten)]
+    item: &'a Item,
+    feed: &'a str,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    highlighted: bool,
+}
+
 fn build_http_client() -> Result<Client> {
     let client = Client::builder()
         .user_agent("rsso")
@@ -53,23 +432,21 @@ fn build_http_client() -> Result<Client> {
 ///
 /// This function solves two problems:
 /// 1. We want to fetch many feeds in parallel.
-/// 2. We cannot hold &mut State or &mut Feed across .await points.
+/// 2. We cannot hold &mut Feed across .await points.
 ///
 /// The solution:
 /// - First: decide *which* feeds need refreshing, and clone those Feed values.
 /// - Second: run all network fetches concurrently using the cloned feeds.
-/// - Third: after all await points, re-borrow `state` mutably and apply results.
-async fn refresh_feeds_concurrent<I>(
-    state: &mut State,
+/// - Third: after all await points, write the results back to the store.
+///
+/// Returns the items seen for the first time.
+async fn refresh_feeds_concurrent(
+    store: &mut dyn Storage,
     cfg: &Config,
     client: &Client,
-    indices: I, // iterable of feed indices, e.g. 0..state.feeds.len()
-) -> Result<()>
-where
-    I: IntoIterator<Item = usize>,
-{
+    feeds: Vec<Feed>,
+) -> Result<Vec<Item>> {
     let now = Utc::now();
-    let refresh_after = Duration::minutes(cfg.refresh_age_mins as i64);
 
     // ---------------------------------------------------------
     // STEP 1: Determine which feeds are stale and clone them.
@@ -81,26 +458,17 @@ where
     // So we clone each stale Feed into a list; these clones will be used
     // purely for network fetching.
     //
-    let mut to_refresh: Vec<(usize, Feed)> = Vec::new();
-
-    for idx in indices {
-        let feed = &state.feeds[idx];
-
-        // Staleness rule: never fetched OR last fetch older than refresh_after
-        let needs_refresh = match feed.last_fetched_at {
-            None => true,
-            Some(last) => now - last >= refresh_after,
-        };
+    let mut to_refresh: Vec<Feed> = Vec::new();
 
-        if needs_refresh {
-            // Clone the feed so we can send it into async tasks
-            to_refresh.push((idx, feed.clone()));
+    for feed in feeds {
+        if needs_refresh(cfg, &feed, now) {
+            to_refresh.push(feed);
         }
     }
 
     // Nothing to do — all feeds are fresh
     if to_refresh.is_empty() {
-        return Ok(());
+        return Ok(Vec::new());
     }
 
     // ---------------------------------------------------------
@@ -117,15 +485,15 @@ where
     //
     let concurrency_limit: usize = 20;
 
-    let results: Vec<(usize, Result<(Option<String>, Vec<Item>)>)> = stream::iter(to_refresh)
-        .map(|(idx, feed_clone)| {
+    let results: Vec<(Feed, FetchResult)> = stream::iter(to_refresh)
+        .map(|feed| {
             // Clone client for use inside the async block
             let client = client.clone();
 
             async move {
                 // Asynchronously fetch using the cloned feed
-                let res = fetch_feed(&client, &feed_clone).await;
-                (idx, res)
+                let res = fetch_feed(&client, &feed).await;
+                (feed, res)
             }
         })
         .buffer_unordered(concurrency_limit)
@@ -133,166 +501,383 @@ where
         .await;
 
     // ---------------------------------------------------------
-    // STEP 3: Apply results back to the real, mutable State.
+    // STEP 3: Apply results back to the store.
     // ---------------------------------------------------------
     //
-    // After all .await points have finished, we now re-borrow
-    // the real feeds/items inside State and update them safely.
-    //
-    // No borrow checker issues here because we only hold &mut references
-    // *after* all async operations are complete.
+    // After all .await points have finished, each result is written
+    // back through the store, one feed at a time.
     //
-    for (idx, fetch_result) in results {
-        let feed = &mut state.feeds[idx];
-
-        match fetch_result {
-            Ok((title_opt, mut new_items)) => {
-                // Update title if provided
-                if let Some(t) = title_opt {
-                    feed.title = Some(t);
-                }
-
-                // Mark feed as successfully fetched
-                feed.last_fetched_at = Some(now);
-                feed.last_error = None;
-
-                // Replace old items for this feed
-                let feed_id = feed.id.clone();
-                state.items.retain(|i| i.feed_id != feed_id);
-
-                // Add the freshly fetched items
-                state.items.append(&mut new_items);
-            }
-
-            Err(err) => {
-                // Mark this feed as failed
-                feed.last_error = Some(err.to_string());
-            }
-        }
+    let mut new_items = Vec::new();
+    for (feed, fetch_result) in results {
+        new_items.extend(apply_fetch_result(store, cfg, feed, fetch_result, now)?);
     }
 
-    Ok(())
+    Ok(new_items)
 }
 
-/// Refresh one feed if its cache is stale
+/// Refresh one feed if its cache is stale, returning the items seen for the
+/// first time
 async fn refresh_feed_if_needed(
-    state: &mut State,
-    feed_index: usize,
+    store: &mut dyn Storage,
+    feed: Feed,
     cfg: &Config,
     client: &Client,
-) -> Result<()> {
+) -> Result<Vec<Item>> {
     let now = Utc::now();
-    let refresh_after = Duration::minutes(cfg.refresh_age_mins as i64);
-
-    // Take a snapshot of the feed to decide if we need to refresh
-    // and to pass to fetch_feed without holding a &mut borrow across .await
-    let (needs_refresh, feed_snapshot) = {
-        let feed = &state.feeds[feed_index];
-        let needs_refresh = match feed.last_fetched_at {
-            None => true,
-            Some(last) => now - last >= refresh_after,
-        };
-        (needs_refresh, feed.clone())
-    };
+    if !needs_refresh(cfg, &feed, now) {
+        return Ok(Vec::new());
+    }
 
-    if !needs_refresh {
-        return Ok(());
+    // Perform the network request asynchronously using our copy of the feed
+    let fetch_result = fetch_feed(client, &feed).await;
+
+    apply_fetch_result(store, cfg, feed, fetch_result, now)
+}
+
+/// How long a feed stays fresh after a fetch: its own refresh interval if it
+/// has one, else `refresh_age_mins`
+fn refresh_interval(cfg: &Config, feed: &Feed) -> Duration {
+    let mins = feed.overrides.refresh_mins.unwrap_or(cfg.refresh_age_mins);
+    refresh_duration(mins)
+}
+
+/// A refresh interval in minutes, capped at `MAX_REFRESH_MINS` so any value
+/// in config.toml or state.json fits a `Duration`
+fn refresh_duration(mins: u64) -> Duration {
+    Duration::minutes(mins.min(MAX_REFRESH_MINS) as i64)
+}
+
+/// When a feed is next due, no earlier than `now`: its refresh interval
+/// after the last fetch, held off for as long as the feed's `<ttl>`,
+/// `sy:updatePeriod` or caching headers ask, and moved out of any hours or
+/// days it asks us to skip. With `ignore_feed_hints`, only the interval
+/// counts. `None` if the feed has never been fetched.
+fn next_refresh_at(cfg: &Config, feed: &Feed, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let last = feed.last_fetched_at?;
+    let mut due = last + refresh_interval(cfg, feed);
+    if cfg.ignore_feed_hints {
+        return Some(due.max(now));
     }
 
-    // Perform the network request asynchronously using the snapshot
-    let fetch_result = fetch_feed(client, &feed_snapshot).await;
+    let hints = &feed.hints;
+    if let Some(interval) = hints.min_interval() {
+        due = due.max(last + interval);
+    }
+    if let Some(fresh_until) = hints.fresh_until {
+        due = due.max(fresh_until);
+    }
+
+    // Step through skipped hours to the next allowed one, giving up after a
+    // week in case every hour is skipped
+    let mut due = due.max(now);
+    for _ in 0..7 * 24 {
+        if !hints.skips(due) {
+            break;
+        }
+        let next_hour = (due + Duration::hours(1)).duration_trunc(Duration::hours(1));
+        due = next_hour.unwrap_or(due + Duration::hours(1));
+    }
+    Some(due)
+}
+
+/// Staleness rule: never fetched, or due according to `next_refresh_at`.
+/// Disabled feeds are never due.
+fn needs_refresh(cfg: &Config, feed: &Feed, now: DateTime<Utc>) -> bool {
+    if feed.overrides.disabled {
+        return false;
+    }
+    next_refresh_at(cfg, feed, now).is_none_or(|due| due <= now)
+}
 
-    // Re-borrow the original feed mutably to apply changes
-    let feed = &mut state.feeds[feed_index];
+/// Record the outcome of fetching one feed in the store.
+///
+/// Fetched items are merged into the feed's existing history (see
+/// `State::merge_items`), which is then trimmed to the feed's history cap
+/// (`max_history_per_feed` unless overridden).
+/// Items that are genuinely new are returned, for webhooks and hooks.
+fn apply_fetch_result(
+    store: &mut dyn Storage,
+    cfg: &Config,
+    mut feed: Feed,
+    fetch_result: FetchResult,
+    now: DateTime<Utc>,
+) -> Result<Vec<Item>> {
+    let mut new_items = Vec::new();
 
     match fetch_result {
-        Ok((title_opt, mut new_items)) => {
-            if let Some(t) = title_opt {
+        Ok(FetchOutcome::NotModified { fresh_until }) => {
+            // Nothing changed upstream; keep the items we already have
+            feed.last_fetched_at = Some(now);
+            feed.last_error = None;
+            feed.hints.fresh_until = fresh_until;
+        }
+        Ok(FetchOutcome::Updated(fetched)) => {
+            // Update title if provided
+            if let Some(t) = fetched.title {
                 feed.title = Some(t);
             }
+
+            let first_fetch = feed.last_fetched_at.is_none();
+
+            // Mark feed as successfully fetched
             feed.last_fetched_at = Some(now);
             feed.last_error = None;
+            feed.etag = fetched.etag;
+            feed.last_modified = fetched.last_modified;
+            feed.hints = fetched.hints;
 
-            // Drop old items for this feed
-            let feed_id = feed.id.clone();
-            state.items.retain(|i| i.feed_id != feed_id);
-
-            // Add the new items
-            state.items.append(&mut new_items);
+            let added = store.upsert_items(&feed.id, fetched.items)?;
 
             // Trim history for this feed so that reads and writes to state file remain
             // snappy
-            let max = cfg.max_history_per_feed;
+            let max_history = feed
+                .overrides
+                .max_history
+                .unwrap_or(cfg.max_history_per_feed);
+            store.trim_history(&feed.id, max_history)?;
 
-            // Gather all items for this feed
-            let mut items_for_feed: Vec<&Item> = state
-                .items
+            let verdicts: Vec<Verdict> = added
                 .iter()
-                .filter(|i| i.feed_id == feed_id)
+                .map(|i| filters::verdict(&cfg.filters, &feed, i))
                 .collect();
+            let auto_read: Vec<String> = added
+                .iter()
+                .zip(&verdicts)
+                .filter(|(_, v)| v.mark_read)
+                .map(|(i, _)| i.id.clone())
+                .collect();
+            if !auto_read.is_empty() {
+                store.mark_read(&auto_read)?;
+            }
 
-            // Sort newest first (uses your existing helper, now for &Item)
-            sort_items_newest_first(&mut items_for_feed);
-
-            // If we exceed the limit, remove the older ones
-            if items_for_feed.len() > max {
-                let to_keep: std::collections::HashSet<_> = items_for_feed
+            // A feed's first fetch is its backlog, not news; and items that
+            // were trimmed straight away are too old to count either. Hidden
+            // items are muted, so they aren't news either
+            if !first_fetch {
+                new_items = added
                     .into_iter()
-                    .take(max)
-                    .map(|i| i as *const Item) // pointer identity
+                    .zip(verdicts)
+                    .filter(|(_, v)| !v.hide)
+                    .map(|(i, _)| i)
+                    .filter(|i| store.find_item(&i.id).is_ok())
                     .collect();
-
-                // Only trim items for this feed, leave other feeds untouched
-                state.items.retain(|i| {
-                    if i.feed_id != feed_id {
-                        true
-                    } else {
-                        // this feed → keep only if pointer is in `to_keep`
-                        let ptr = i as *const Item;
-                        to_keep.contains(&ptr)
-                    }
-                });
             }
         }
         Err(err) => {
+            // Mark this feed as failed
             feed.last_error = Some(err.to_string());
         }
     }
 
-    Ok(())
+    store.update_feed(&feed)?;
+    Ok(new_items)
 }
 
-fn build_feed_label_map(state: &State) -> HashMap<String, String> {
-    let mut map = HashMap::new();
+/// Run the global and per-feed `on_new_item` hooks for each new item.
+///
+/// Call this after the state is saved and the lock released: hooks can take
+/// a while, and may run rsso themselves. A failing hook is reported but
+/// doesn't stop the others.
+pub fn run_new_item_hooks(cfg: &Config, feeds: &[Feed], items: &[Item]) {
+    for item in items {
+        let Some(feed) = feeds.iter().find(|f| f.id == item.feed_id) else {
+            continue;
+        };
+        let hooks: Vec<&str> = cfg
+            .settings_for(feed)
+            .and_then(|s| s.on_new_item.as_deref())
+            .into_iter()
+            .chain(cfg.on_new_item.as_deref())
+            .collect();
+        if hooks.is_empty() {
+            continue;
+        }
 
-    for f in &state.feeds {
-        // Same label logic you had in print_item_line before
-        let label = f
-            .alias
-            .clone()
-            .or_else(|| f.title.clone())
-            .unwrap_or_else(|| f.id.clone());
+        let label = feed_label(feed);
+        let record = ItemRecord {
+            item,
+            feed: &label,
+            highlighted: false,
+        };
+        let json = match serde_json::to_string(&record) {
+            Ok(json) => json,
+            Err(err) => {
+                eprintln!("Could not serialize item {}: {}", item.short_id(), err);
+                continue;
+            }
+        };
 
-        map.insert(f.id.clone(), label);
+        for hook in &hooks {
+            if let Err(err) = run_hook(hook, item, &label, &json) {
+                eprintln!("{:#}", err);
+            }
+        }
     }
+}
 
-    map
+/// Log a delivery of each new item to every webhook that wants it, then take
+/// on every delivery still pending, including failures from earlier runs.
+///
+/// Taken deliveries are marked in the log, so a run alongside this one
+/// leaves them alone until `webhooks::IN_FLIGHT_MINS` have passed.
+/// Deliveries to webhooks since removed from config are given up on.
+fn queue_deliveries(
+    store: &mut dyn Storage,
+    cfg: &Config,
+    new_items: &[Item],
+    now: DateTime<Utc>,
+) -> Result<Vec<PendingDelivery>> {
+    for item in new_items {
+        let Some(feed) = store.feeds().iter().find(|f| f.id == item.feed_id) else {
+            continue;
+        };
+        let hooks: Vec<&Webhook> = cfg.webhooks.iter().filter(|h| h.matches(feed)).collect();
+        for hook in hooks {
+            store.save_delivery(&Delivery::new(&hook.url, item, now))?;
+        }
+    }
+
+    let label_map = build_feed_label_map(store.feeds());
+    let taken_since = now - Duration::minutes(webhooks::IN_FLIGHT_MINS);
+    let mut pending = Vec::new();
+    for mut delivery in store.deliveries()? {
+        if delivery.delivered_at.is_some() || delivery.attempts >= webhooks::MAX_ATTEMPTS {
+            continue;
+        }
+        let in_flight = delivery.last_error.as_deref() == Some(webhooks::IN_FLIGHT)
+            && delivery.last_attempt_at.is_some_and(|at| at > taken_since);
+        if in_flight {
+            continue;
+        }
+        let Some(hook) = cfg.webhooks.iter().find(|h| h.url == delivery.webhook) else {
+            delivery.attempts = webhooks::MAX_ATTEMPTS;
+            delivery.last_error = Some("Webhook is no longer configured".to_string());
+            store.save_delivery(&delivery)?;
+            continue;
+        };
+
+        match store.find_item(&delivery.item_id) {
+            Ok(item) => {
+                let label = label_map
+                    .get(&item.feed_id)
+                    .map(|s| s.as_str())
+                    .unwrap_or(&item.feed_id);
+                let body = webhook_body(hook, &item, label)?;
+
+                let mut taken = delivery.clone();
+                taken.last_attempt_at = Some(now);
+                taken.last_error = Some(webhooks::IN_FLIGHT.to_string());
+                store.save_delivery(&taken)?;
+
+                pending.push(PendingDelivery {
+                    delivery,
+                    hook: hook.clone(),
+                    body,
+                });
+            }
+            Err(_) => {
+                delivery.attempts = webhooks::MAX_ATTEMPTS;
+                delivery.last_error = Some("Item is no longer stored".to_string());
+                store.save_delivery(&delivery)?;
+            }
+        }
+    }
+
+    store.trim_deliveries(webhooks::MAX_LOG_ENTRIES)?;
+    Ok(pending)
+}
+
+/// POST each pending delivery, with up to `tries` requests each
+async fn post_deliveries(
+    client: &Client,
+    pending: Vec<PendingDelivery>,
+    tries: u32,
+) -> Vec<(Delivery, PostOutcome)> {
+    stream::iter(pending)
+        .map(|p| async move {
+            let tries = tries.min(webhooks::MAX_ATTEMPTS - p.delivery.attempts);
+            let outcome = webhooks::post(client, &p.hook, &p.body, tries).await;
+            (p.delivery, outcome)
+        })
+        .buffer_unordered(8)
+        .collect()
+        .await
+}
+
+/// Write how each delivery went to the log.
+///
+/// Failures are recorded (and reported) rather than failing the command,
+/// and are tried again by later runs.
+fn record_deliveries(
+    store: &mut dyn Storage,
+    results: Vec<(Delivery, PostOutcome)>,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    for (mut delivery, outcome) in results {
+        delivery.attempts += outcome.tries;
+        delivery.last_attempt_at = Some(now);
+        match outcome.error {
+            None => {
+                delivery.delivered_at = Some(now);
+                delivery.last_error = None;
+            }
+            Some(err) => {
+                eprintln!(
+                    "Webhook {} failed for '{}': {}",
+                    delivery.webhook, delivery.item_title, err
+                );
+                delivery.last_error = Some(err);
+            }
+        }
+        store.save_delivery(&delivery)?;
+    }
+
+    store.trim_deliveries(webhooks::MAX_LOG_ENTRIES)
+}
+
+/// The request body for one item: its template rendered (with values
+/// escaped for JSON bodies), or the item as JSON
+fn webhook_body(hook: &Webhook, item: &Item, feed_label: &str) -> Result<String> {
+    Ok(match &hook.template {
+        Some(t) if hook.content_type.contains("json") => t.render_plain(item, feed_label, |v| {
+            let quoted = serde_json::Value::from(v).to_string();
+            quoted[1..quoted.len() - 1].to_string()
+        }),
+        Some(t) => t.render_plain(item, feed_label, |v| v.to_string()),
+        None => serde_json::to_string(&ItemRecord {
+            item,
+            feed: feed_label,
+            highlighted: false,
+        })?,
+    })
+}
+
+/// How a feed is named in listings: its label, else alias, else title, else
+/// id
+fn feed_label(f: &Feed) -> String {
+    f.overrides
+        .label
+        .clone()
+        .or_else(|| f.alias.clone())
+        .or_else(|| f.title.clone())
+        .unwrap_or_else(|| f.id.clone())
 }
 
-/// Print a single item in pipe-friendly format
-fn print_item_line(item: &Item, feed_label: &str, cfg: &Config) {
-    let date = item
-        .published_at
-        .unwrap_or(item.updated_at.unwrap_or(item.first_seen_at))
-        .format("%d %b %y")
-        .to_string();
+fn build_feed_label_map(feeds: &[Feed]) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+
+    for f in feeds {
+        map.insert(f.id.clone(), feed_label(f));
+    }
+
+    map
+}
 
+/// Print a single item in pipe-friendly format, laid out by the item template
+fn print_item_line(record: &ItemRecord, template: &Template, cfg: &Config) {
     println!(
-        "{} | {} | {} | {}",
-        date,
-        feed_label,
-        item.title.bold(),
-        item.link.blue()
+        "{}",
+        template.render(record.item, record.feed, record.highlighted)
     );
 
     if cfg.new_line_between_items {
@@ -300,49 +885,322 @@ fn print_item_line(item: &Item, feed_label: &str, cfg: &Config) {
     }
 }
 
-/// Sort items - first by published, then updated, and finally by first_seen_at
-fn sort_items_newest_first(items: &mut Vec<&Item>) {
-    items.sort_by(|a, b| {
-        let a_date = a
-            .published_at
-            .unwrap_or(a.updated_at.unwrap_or(a.first_seen_at));
-        let b_date = b
-            .published_at
-            .unwrap_or(b.updated_at.unwrap_or(b.first_seen_at));
-        b_date.cmp(&a_date)
+/// Print items newest first (already sorted), as text lines or JSON records,
+/// highlighting those picked out by `[[filters]]` rules
+fn print_items(items: &[Item], feeds: &[Feed], view: &ViewOptions, cfg: &Config) -> Result<()> {
+    let label_map = build_feed_label_map(feeds);
+    let labelled = items.iter().map(|item| {
+        // Look up label by feed_id; fall back to the feed_id itself if missing
+        let feed = label_map
+            .get(&item.feed_id)
+            .map(|s| s.as_str())
+            .unwrap_or(&item.feed_id);
+        let highlighted = feeds
+            .iter()
+            .find(|f| f.id == item.feed_id)
+            .is_some_and(|f| filters::verdict(&cfg.filters, f, item).highlight);
+        ItemRecord {
+            item,
+            feed,
+            highlighted,
+        }
     });
+
+    if view.format == OutputFormat::Text {
+        for record in labelled {
+            print_item_line(&record, &view.template, cfg);
+        }
+        Ok(())
+    } else {
+        let records: Vec<ItemRecord> = labelled.collect();
+        print_records(&records, view.format)
+    }
+}
+
+/// The items for a listing, leaving out those hidden by `[[filters]]` rules
+/// before the query's limit is applied
+fn listed_items(store: &dyn Storage, cfg: &Config, mut query: ItemQuery) -> Result<Vec<Item>> {
+    if !hides_any(cfg) {
+        return store.items(&query);
+    }
+    visible_pages(store, cfg, query.limit, |offset| {
+        query.offset = offset;
+        store.items(&query)
+    })
+}
+
+/// Search results, leaving out items hidden by `[[filters]]` rules before
+/// the query's limit is applied
+fn searched_items(store: &dyn Storage, cfg: &Config, mut query: SearchQuery) -> Result<Vec<Item>> {
+    if !hides_any(cfg) {
+        return store.search(&query);
+    }
+    visible_pages(store, cfg, query.limit, |offset| {
+        query.offset = offset;
+        store.search(&query)
+    })
+}
+
+fn hides_any(cfg: &Config) -> bool {
+    cfg.filters.iter().any(|f| f.action == FilterAction::Hide)
+}
+
+/// Up to `limit` items that aren't hidden, fetching `limit` at a time
+/// (from `page(offset)`) until there are enough or no more
+fn visible_pages(
+    store: &dyn Storage,
+    cfg: &Config,
+    limit: Option<usize>,
+    mut page: impl FnMut(usize) -> Result<Vec<Item>>,
+) -> Result<Vec<Item>> {
+    let feeds: HashMap<&str, &Feed> = store.feeds().iter().map(|f| (f.id.as_str(), f)).collect();
+    let visible = |item: &Item| {
+        feeds
+            .get(item.feed_id.as_str())
+            .is_none_or(|f| !filters::verdict(&cfg.filters, f, item).hide)
+    };
+
+    let mut items = Vec::new();
+    let mut offset = 0;
+    loop {
+        let fetched = page(offset)?;
+        let last = limit.is_none_or(|limit| fetched.len() < limit);
+        offset += fetched.len();
+        items.extend(fetched.into_iter().filter(|i| visible(i)));
+
+        if let Some(limit) = limit
+            && items.len() >= limit
+        {
+            items.truncate(limit);
+            return Ok(items);
+        }
+        if last {
+            return Ok(items);
+        }
+    }
+}
+
+/// Print records as a JSON array or as newline-delimited JSON
+fn print_records<T: Serialize>(records: &[T], format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(records)?),
+        OutputFormat::Ndjson => {
+            for r in records {
+                println!("{}", serde_json::to_string(r)?);
+            }
+        }
+        OutputFormat::Text => unreachable!("text output is printed by the caller"),
+    }
+    Ok(())
 }
 
 // COMMANDS
 
-/// Subscribe to a new feed
-fn cmd_sub(state: &mut State, url: &str, alias: Option<String>) -> Result<()> {
-    // crude id: use alias if provided, otherwise derive from URL
-    let id = alias.clone().unwrap_or_else(|| {
+/// Crude feed id: use alias if provided, otherwise derive from URL
+fn derive_feed_id(url: &str, alias: Option<&str>) -> String {
+    alias.map(|a| a.to_string()).unwrap_or_else(|| {
         url.replace("https://", "")
             .replace("http://", "")
             .trim_end_matches('/')
             .replace('/', "-")
-    });
+    })
+}
 
-    let feed = Feed {
-        id: id.clone(),
-        url: url.to_string(),
-        alias,
-        title: None, // will be filled on first fetch
-        added_at: Utc::now(),
-        last_fetched_at: None,
-        last_error: None,
+/// `id`, or if a feed already has it (or has it as an alias, in any case),
+/// `id-2`, `id-3` and so on
+fn unique_feed_id(store: &dyn Storage, id: &str) -> String {
+    let taken = |candidate: &str| {
+        store.feeds().iter().any(|f| f.id == candidate) || alias_in_use(store, candidate)
     };
+    if !taken(id) {
+        return id.to_string();
+    }
+    (2..)
+        .map(|n| format!("{id}-{n}"))
+        .find(|candidate| !taken(candidate))
+        .expect("some suffix is free")
+}
 
-    state.add_feed(feed)?;
-    println!("Subscribed to {}", url);
-    Ok(())
+/// Ids of one feed (matched among the selected feeds) or of every selected feed
+fn selected_feed_ids(
+    store: &dyn Storage,
+    key: Option<&str>,
+    selector: &FeedSelector,
+) -> Result<Vec<String>> {
+    Ok(match key {
+        Some(key) => match store.find_selected_feed(key, selector) {
+            Some(f) => vec![f.id],
+            None => bail!("No matching feed for '{}'", key),
+        },
+        None => store
+            .select_feeds(selector)
+            .into_iter()
+            .map(|f| f.id)
+            .collect(),
+    })
+}
+
+/// Ids of the feeds the default view lists: the selected ones, minus any
+/// that are disabled
+fn listed_feed_ids(store: &dyn Storage, selector: &FeedSelector) -> Vec<String> {
+    store
+        .select_feeds(selector)
+        .into_iter()
+        .filter(|f| !f.overrides.disabled)
+        .map(|f| f.id)
+        .collect()
+}
+
+/// The feed to subscribe to for `url`.
+///
+/// If `url` is a web page rather than a feed, look for the feed(s) it
+/// advertises (or lives at a common path like /feed), asking which one if
+/// there are several. A feed found at a common path is only taken once the
+/// user agrees. Unless `force` is set, a URL with no feed is refused.
+async fn find_feed(client: &Client, url: &str, force: bool) -> Result<FeedCandidate> {
+    let as_given = || FeedCandidate {
+        url: url.to_string(),
+        title: None,
+        document: None,
+        guessed: false,
+    };
+
+    let found = match discover_feeds(client, url).await {
+        Ok(mut candidates) => match candidates.len() {
+            0 if force => as_given(),
+            0 => bail!(
+                "No feed found: {} isn't a feed and doesn't link to one \
+                 (use --force to subscribe anyway)",
+                url
+            ),
+            1 if candidates[0].guessed => confirm_guess(url, candidates.remove(0))?,
+            1 => candidates.remove(0),
+            _ => pick_candidate(url, &candidates)?.clone(),
+        },
+        Err(err) if force => {
+            eprintln!("Warning: {}; subscribing to {} anyway", err, url);
+            as_given()
+        }
+        Err(err) => bail!(
+            "Not subscribing to {}: {} (use --force to subscribe anyway)",
+            url,
+            err
+        ),
+    };
+    if found.url != url {
+        println!("Found feed at {}", found.url);
+    }
+    Ok(found)
+}
+
+/// Refuse to subscribe to `url` twice, or to give a new feed the id of one
+/// we have
+fn check_not_subscribed(store: &dyn Storage, url: &str, id: Option<&str>) -> Result<()> {
+    if let Some(f) = store.feeds().iter().find(|f| f.url == url) {
+        bail!("Already subscribed to {} (as {})", url, feed_label(f));
+    }
+    if let Some(id) = id.filter(|id| store.feeds().iter().any(|f| f.id == *id)) {
+        bail!(
+            "There's already a feed called '{}'; pick another --alias",
+            id
+        );
+    }
+    Ok(())
+}
+
+/// Add a new subscription, with the result of its first fetch (see
+/// `run_sub`), which fills in the feed's title and first items
+fn cmd_sub(
+    store: &mut dyn Storage,
+    cfg: &Config,
+    feed: Feed,
+    fetch_result: FetchResult,
+) -> Result<()> {
+    check_not_subscribed(store, &feed.url, Some(&feed.id))?;
+
+    let url = feed.url.clone();
+    store.add_feed(feed.clone())?;
+    apply_fetch_result(store, cfg, feed, fetch_result, Utc::now())?;
+
+    let feed = store
+        .find_feed(&url)
+        .context("Feed vanished after subscribing")?;
+    match &feed.title {
+        Some(title) => println!("Subscribed to {} ({})", title, url),
+        None => println!("Subscribed to {}", url),
+    }
+    Ok(())
+}
+
+/// Ask whether a feed found at a common path, rather than linked from the
+/// page, is the one wanted
+fn confirm_guess(url: &str, guess: FeedCandidate) -> Result<FeedCandidate> {
+    let found = match &guess.title {
+        Some(title) => format!("{} ({})", guess.url, title),
+        None => guess.url.clone(),
+    };
+    if !io::stdin().is_terminal() {
+        bail!(
+            "{} doesn't link to a feed, but there is one at {}; subscribe to that \
+             directly if it's the one you want",
+            url,
+            guess.url
+        );
+    }
+
+    let prompt = format!(
+        "{} doesn't link to a feed, but there is one at {}.\nSubscribe to it? [y/N] ",
+        url, found
+    );
+    if !state::confirm(&prompt)? {
+        bail!("Not subscribing to {}", url);
+    }
+    Ok(guess)
+}
+
+/// Ask which of several discovered feeds to subscribe to
+fn pick_candidate<'a>(url: &str, candidates: &'a [FeedCandidate]) -> Result<&'a FeedCandidate> {
+    let describe = |c: &FeedCandidate| match &c.title {
+        Some(title) => format!("{} ({})", title, c.url),
+        None => c.url.clone(),
+    };
+
+    if !io::stdin().is_terminal() {
+        let list: Vec<String> = candidates
+            .iter()
+            .map(|c| format!("- {}", describe(c)))
+            .collect();
+        bail!(
+            "{} offers several feeds; subscribe to one of them directly:\n{}",
+            url,
+            list.join("\n")
+        );
+    }
+
+    println!("{} offers several feeds:", url);
+    for (i, c) in candidates.iter().enumerate() {
+        println!("  {}) {}", i + 1, describe(c));
+    }
+
+    loop {
+        print!("Subscribe to which one? [1-{}] ", candidates.len());
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            bail!("No feed selected");
+        }
+
+        match answer.trim().parse::<usize>() {
+            Ok(n) if (1..=candidates.len()).contains(&n) => return Ok(&candidates[n - 1]),
+            _ => println!("Please enter a number between 1 and {}", candidates.len()),
+        }
+    }
 }
 
 /// Unsubscribe from a feed using alias/title/id/url
-fn cmd_unsub(state: &mut State, key: &str) -> Result<()> {
-    let removed = state.remove_feed(key);
+fn cmd_unsub(store: &mut dyn Storage, key: &str) -> Result<()> {
+    let removed = store.remove_feed(key)?;
     if removed == 0 {
         bail!("No matching feed for '{}'", key);
     } else {
@@ -352,16 +1210,24 @@ fn cmd_unsub(state: &mut State, key: &str) -> Result<()> {
 }
 
 /// List subscribed feeds with status
-fn cmd_list(state: &State) -> Result<()> {
-    if state.feeds.is_empty() {
+fn cmd_list(store: &dyn Storage, selector: &FeedSelector, format: OutputFormat) -> Result<()> {
+    let feeds = store.select_feeds(selector);
+
+    if format != OutputFormat::Text {
+        return print_records(&feeds, format);
+    }
+
+    if store.feeds().is_empty() {
         println!("No feeds subscribed. Use `rsso sub <url>` to add one.");
         return Ok(());
     }
 
-    for f in &state.feeds {
+    for f in feeds {
         let id = &f.id;
         let name = f.title.as_deref().unwrap_or(&f.url);
-        let status = if let Some(err) = &f.last_error {
+        let status = if f.overrides.disabled {
+            "Disabled".to_string()
+        } else if let Some(err) = &f.last_error {
             format!("ERROR: {}", err)
         } else if let Some(last) = f.last_fetched_at {
             format!("OK (last fetched: {})", last.to_rfc3339())
@@ -369,174 +1235,806 @@ fn cmd_list(state: &State) -> Result<()> {
             "Never fetched".to_string()
         };
 
-        println!("{id} | {name} | {} | {status}", f.url);
+        // Group and tags only get a column when the feed has any
+        let mut labels: Vec<String> = Vec::new();
+        if let Some(group) = &f.group {
+            labels.push(format!("{group}/"));
+        }
+        labels.extend(f.tags.iter().map(|t| format!("#{t}")));
+
+        if labels.is_empty() {
+            println!("{id} | {name} | {} | {status}", f.url);
+        } else {
+            println!(
+                "{id} | {name} | {} | {status} | {}",
+                f.url,
+                labels.join(" ")
+            );
+        }
     }
     Ok(())
 }
 
 /// Rename a feed's alias (and internal id), matched by alias/title/id/url
-fn cmd_rename(state: &mut State, key: &str, new_alias: &str) -> Result<()> {
+fn cmd_rename(store: &mut dyn Storage, key: &str, new_alias: &str) -> Result<()> {
     let new_alias = new_alias.trim();
     if new_alias.is_empty() {
         bail!("Alias cannot be empty");
     }
 
-    // Make sure no other feed already uses this alias (case-insensitive)
-    let new_lower = new_alias.to_lowercase();
-    if state
-        .feeds
-        .iter()
-        .any(|f| f.alias.as_ref().map(|a| a.to_lowercase()).as_deref() == Some(new_lower.as_str()))
-    {
+    if alias_in_use(store, new_alias) {
         bail!("Alias '{}' is already in use", new_alias);
     }
 
     // Find the feed by alias/title/id/url
-    let idx = match state.find_feed_index(key) {
-        Some(i) => i,
+    let mut feed = match store.find_feed(key) {
+        Some(f) => f,
         None => bail!("No matching feed for '{}'", key),
     };
 
-    let old_id = state.feeds[idx].id.clone();
+    // Update alias and id to the new alias; the store moves its items along
+    feed.alias = Some(new_alias.to_string());
+    feed.id = new_alias.to_string();
+    store.update_feed(&feed)?;
+
+    println!("Renamed feed '{}' to alias '{}'", key, new_alias);
+    Ok(())
+}
+
+/// Whether a feed already uses this alias, ignoring case
+fn alias_in_use(store: &dyn Storage, alias: &str) -> bool {
+    let lower = alias.to_lowercase();
+    store
+        .feeds()
+        .iter()
+        .any(|f| f.alias.as_ref().is_some_and(|a| a.to_lowercase() == lower))
+}
+
+/// Add tags to a feed
+fn cmd_tag(store: &mut dyn Storage, key: &str, tags: &[String]) -> Result<()> {
+    let mut feed = match store.find_feed(key) {
+        Some(f) => f,
+        None => bail!("No matching feed for '{}'", key),
+    };
+    for tag in tags {
+        let tag = normalize_tag(tag);
+        if tag.is_empty() {
+            bail!("Tags cannot be empty");
+        }
+        feed.tags.insert(tag);
+    }
 
-    // Update alias and id to the new alias
-    state.feeds[idx].alias = Some(new_alias.to_string());
-    state.feeds[idx].id = new_alias.to_string();
+    store.update_feed(&feed)?;
+    print_feed_tags(&feed);
+    Ok(())
+}
 
-    // Update items to reference the new feed id
-    for item in state.items.iter_mut() {
-        if item.feed_id == old_id {
-            item.feed_id = new_alias.to_string();
+/// Remove tags from a feed
+fn cmd_untag(store: &mut dyn Storage, key: &str, tags: &[String]) -> Result<()> {
+    let mut feed = match store.find_feed(key) {
+        Some(f) => f,
+        None => bail!("No matching feed for '{}'", key),
+    };
+    for tag in tags {
+        if !feed.tags.remove(&normalize_tag(tag)) {
+            eprintln!("Feed '{}' is not tagged '{}'", key, tag);
         }
     }
 
-    println!("Renamed feed '{}' to alias '{}'", key, new_alias);
+    store.update_feed(&feed)?;
+    print_feed_tags(&feed);
     Ok(())
 }
 
-/// Default `rsso` behaviour: show recent items across all feeds
-async fn cmd_show_all(state: &mut State, cfg: &Config, limit: usize) -> Result<()> {
-    if state.feeds.is_empty() {
-        println!("No feeds subscribed. Use `rsso sub <url>` to add one.");
+fn print_feed_tags(feed: &Feed) {
+    let label = feed
+        .alias
+        .as_deref()
+        .or(feed.title.as_deref())
+        .unwrap_or(&feed.id);
+
+    if feed.tags.is_empty() {
+        println!("{} has no tags", label);
+    } else {
+        let tags: Vec<&str> = feed.tags.iter().map(|t| t.as_str()).collect();
+        println!("{} tags: {}", label, tags.join(", "));
+    }
+}
+
+/// Set or clear a feed's group
+fn cmd_group(store: &mut dyn Storage, key: &str, group: Option<&str>) -> Result<()> {
+    let mut feed = match store.find_feed(key) {
+        Some(f) => f,
+        None => bail!("No matching feed for '{}'", key),
+    };
+
+    let group = group
+        .map(|g| g.trim().trim_matches('/'))
+        .filter(|g| !g.is_empty());
+    feed.group = group.map(|g| g.to_string());
+    store.update_feed(&feed)?;
+
+    match group {
+        Some(g) => println!("Moved '{}' to group '{}'", key, g),
+        None => println!("Removed '{}' from its group", key),
+    }
+    Ok(())
+}
+
+/// Override settings for one feed from `key=value` pairs; an empty value
+/// goes back to the config setting. With no pairs, shows the overrides.
+fn cmd_set(store: &mut dyn Storage, key: &str, settings: &[String]) -> Result<()> {
+    let mut feed = match store.find_feed(key) {
+        Some(f) => f,
+        None => bail!("No matching feed for '{}'", key),
+    };
+
+    for setting in settings {
+        let Some((name, value)) = setting.split_once('=') else {
+            bail!("Expected setting=value, got '{}'", setting);
+        };
+        let value = value.trim();
+        let o = &mut feed.overrides;
+        match name.trim() {
+            "refresh" if value.is_empty() => o.refresh_mins = None,
+            "refresh" => o.refresh_mins = Some(parse_refresh(value)?),
+            "history" => o.max_history = parse_count(value, "history")?,
+            "limit" => o.limit = parse_count(value, "limit")?,
+            "label" => o.label = Some(value.to_string()).filter(|v| !v.is_empty()),
+            "disabled" => {
+                o.disabled = match value {
+                    "" | "false" | "no" | "off" => false,
+                    "true" | "yes" | "on" => true,
+                    _ => bail!("Expected disabled=true or disabled=false, got '{}'", value),
+                }
+            }
+            other => bail!(
+                "Unknown setting '{}' (use refresh, history, limit, label or disabled)",
+                other
+            ),
+        }
+    }
+
+    if !settings.is_empty() {
+        store.update_feed(&feed)?;
+    }
+    print_feed_overrides(&feed);
+    Ok(())
+}
+
+/// Longest refresh interval `rsso set` accepts, and that any other is cut
+/// down to
+const MAX_REFRESH_MINS: u64 = 365 * 24 * 60;
+
+/// A refresh interval for `rsso set`, in minutes: a bare number of minutes
+/// (like `refresh_age_mins`) or a span like 10m, 2h or 1d
+fn parse_refresh(value: &str) -> Result<u64> {
+    let mins = match value.parse::<u64>() {
+        Ok(mins) => Some(mins),
+        Err(_) => u64::try_from(parse_span(value)?.num_minutes()).ok(),
+    };
+    match mins {
+        Some(mins) if mins <= MAX_REFRESH_MINS => Ok(mins),
+        _ => bail!("Invalid refresh interval '{}': at most 365d", value),
+    }
+}
+
+/// A count for `rsso set`, or `None` for an empty value
+fn parse_count(value: &str, name: &str) -> Result<Option<usize>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match value.parse() {
+        Ok(n) => Ok(Some(n)),
+        Err(_) => bail!("Expected a number for {}, got '{}'", name, value),
+    }
+}
+
+fn print_feed_overrides(feed: &Feed) {
+    let o = &feed.overrides;
+    let mut shown: Vec<String> = Vec::new();
+    if let Some(mins) = o.refresh_mins {
+        let span = match mins {
+            m if m > 0 && m % (24 * 60) == 0 => format!("{}d", m / (24 * 60)),
+            m if m > 0 && m % 60 == 0 => format!("{}h", m / 60),
+            m => format!("{}m", m),
+        };
+        shown.push(format!("refresh={span}"));
+    }
+    if let Some(n) = o.max_history {
+        shown.push(format!("history={n}"));
+    }
+    if let Some(n) = o.limit {
+        shown.push(format!("limit={n}"));
+    }
+    if let Some(label) = &o.label {
+        shown.push(format!("label={label}"));
+    }
+    if o.disabled {
+        shown.push("disabled=true".to_string());
+    }
+
+    let name = feed.alias.as_deref().unwrap_or(&feed.id);
+    if shown.is_empty() {
+        println!("{} uses the config settings", name);
+    } else {
+        println!("{} settings: {}", name, shown.join(" "));
+    }
+}
+
+/// Mark individual items as read
+fn cmd_read(store: &mut dyn Storage, keys: &[String]) -> Result<()> {
+    let mut missing = 0;
+
+    for key in keys {
+        match store.find_item(key) {
+            Ok(item) => {
+                store.mark_read(std::slice::from_ref(&item.id))?;
+                println!("Marked read: {}", item.title);
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                missing += 1;
+            }
+        }
+    }
+
+    if missing == keys.len() {
+        bail!("No items marked read");
+    }
+    Ok(())
+}
+
+/// Pick the items `rsso open` opens.
+///
+/// Items are picked by id, or as `@N` by position in the listing (newest
+/// first) of the selected feeds or `--feed`, honouring `--unread`. With no
+/// items given, the first `limit` items of that listing are picked. Opening
+/// them and marking them read waits for `run_follow_up`, so a browser that
+/// takes its time doesn't hold the lock.
+fn cmd_open(
+    store: &dyn Storage,
+    cfg: &Config,
+    keys: &[String],
+    feed_key: Option<&str>,
+    selector: &FeedSelector,
+    view: &ViewOptions,
+) -> Result<Vec<Item>> {
+    // Same feeds as the listing being referred to: `rsso feed <key>` or the
+    // default view
+    let feed_ids = match feed_key {
+        Some(_) => selected_feed_ids(store, feed_key, selector)?,
+        None => listed_feed_ids(store, selector),
+    };
+
+    // The listing positions refer to, without refreshing anything first.
+    // If printing that listing moved the last-run mark on, look from where
+    // it started.
+    let mut query = view.item_query(store, feed_ids, None)?;
+    if let Some(When::LastRun) = view.since
+        && feed_key.is_none()
+        && shows_all_new(view, selector)
+    {
+        query.seen_after = store.previous_viewed_at()?;
+    }
+    let listing = listed_items(store, cfg, query)?;
+
+    if keys.is_empty() {
+        let to_open: Vec<Item> = listing.into_iter().take(view.limit).collect();
+        if to_open.is_empty() {
+            println!("No items to open.");
+        }
+        return Ok(to_open);
+    }
+
+    let mut to_open = Vec::new();
+    for key in keys {
+        let Some(position) = key.strip_prefix('@') else {
+            match store.find_item(key) {
+                Ok(item) => to_open.push(item),
+                // Plain numbers used to be positions
+                Err(err) if key.bytes().all(|b| b.is_ascii_digit()) => {
+                    bail!("{}; to open by position in the listing, use @{}", err, key)
+                }
+                Err(err) => return Err(err),
+            }
+            continue;
+        };
+
+        let n = position
+            .parse::<usize>()
+            .ok()
+            .filter(|&n| n >= 1)
+            .with_context(|| format!("Invalid position '{}'; positions start at @1", key))?;
+        match listing.get(n - 1) {
+            Some(item) => to_open.push(item.clone()),
+            None => bail!("No item at position {} (listing has {})", n, listing.len()),
+        }
+    }
+    Ok(to_open)
+}
+
+/// Open items in the browser, stopping at the first that fails. Returns
+/// the ids of those opened, to be marked read, and the failure if any.
+fn open_items(cfg: &Config, items: &[Item]) -> (Vec<String>, Result<()>) {
+    let mut opened = Vec::new();
+    for item in items {
+        if let Err(err) = open_link(cfg, &item.link) {
+            return (opened, Err(err));
+        }
+        println!("Opened: {}", item.title);
+        opened.push(item.id.clone());
+    }
+    (opened, Ok(()))
+}
+
+/// Search stored items and print the best matches first.
+///
+/// `feed:` and `tag:` qualifiers narrow the feeds further within any
+/// `--tag`/`--group` selection.
+fn cmd_search(
+    store: &dyn Storage,
+    cfg: &Config,
+    query: &str,
+    selector: &FeedSelector,
+    view: &ViewOptions,
+) -> Result<()> {
+    let parsed = search::parse_query(query)?;
+    if parsed.terms.is_empty() {
+        bail!("Nothing to search for; add some words to the query");
+    }
+
+    let feed_ids: Vec<String> = store
+        .select_feeds(selector)
+        .into_iter()
+        .filter(|f| parsed.feeds.is_empty() || parsed.feeds.iter().any(|k| f.matches_key(k)))
+        .filter(|f| {
+            parsed.tags.is_empty()
+                || parsed
+                    .tags
+                    .iter()
+                    .any(|t| f.tags.contains(&normalize_tag(t)))
+        })
+        .map(|f| f.id)
+        .collect();
+
+    let items = searched_items(
+        store,
+        cfg,
+        SearchQuery {
+            terms: parsed.terms,
+            feed_ids: Some(feed_ids),
+            unread_only: view.unread_only,
+            before: parsed.before,
+            after: parsed.after,
+            limit: Some(view.limit),
+            offset: 0,
+        },
+    )?;
+
+    if items.is_empty() && view.format == OutputFormat::Text {
+        eprintln!("No items match '{}'", query);
         return Ok(());
     }
 
-    // Build a shared HTTP client
+    print_items(&items, store.feeds(), view, cfg)
+}
+
+/// Print an item's header and its full content rendered for the terminal
+fn cmd_show(store: &mut dyn Storage, key: &str, width: Option<usize>) -> Result<()> {
+    let item = store.find_item(key)?;
+    let label_map = build_feed_label_map(store.feeds());
+
+    let feed_label = label_map
+        .get(&item.feed_id)
+        .map(|s| s.as_str())
+        .unwrap_or(&item.feed_id);
+
+    let width = width
+        .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+        .unwrap_or(80)
+        .clamp(20, 100);
+
+    println!("{}", item.title.bold());
+    let mut meta = vec![
+        feed_label.to_string(),
+        item.date().format("%d %b %Y").to_string(),
+    ];
+    if let Some(author) = &item.author {
+        meta.push(author.clone());
+    }
+    println!("{}", meta.join(" | ").dimmed());
+    println!("{}", item.link.blue());
+    println!();
+
+    // Fall back to the summary for feeds that don't include full content
+    match item.content.as_deref().or(item.summary.as_deref()) {
+        Some(html) => print!("{}", render_html(html, width)?),
+        None => println!(
+            "{}",
+            "(no content in feed; use `rsso open` to read it)".dimmed()
+        ),
+    }
+
+    store.mark_read(&[item.id])?;
+    Ok(())
+}
+
+/// Mark all items in one feed, or in every selected feed, as read
+fn cmd_mark_read(
+    store: &mut dyn Storage,
+    key: Option<&str>,
+    selector: &FeedSelector,
+) -> Result<()> {
+    let feed_ids = selected_feed_ids(store, key, selector)?;
+
+    let unread: Vec<String> = store
+        .items(&ItemQuery {
+            feed_ids: Some(feed_ids),
+            unread_only: true,
+            ..ItemQuery::default()
+        })?
+        .into_iter()
+        .map(|i| i.id)
+        .collect();
+    let count = store.mark_read(&unread)?;
+
+    println!("Marked {} item(s) as read", count);
+    Ok(())
+}
+
+/// Import feeds from an OPML file, skipping any we are already subscribed to
+fn cmd_import(store: &mut dyn Storage, path: &Path) -> Result<()> {
+    let contents = fs::read_to_string(path)?;
+    let outlines = opml::parse_opml(&contents)?;
+
+    let mut imported = 0;
+    let mut skipped: Vec<String> = Vec::new();
+
+    for o in outlines {
+        if store.feeds().iter().any(|f| f.url == o.url) {
+            skipped.push(o.url);
+            continue;
+        }
+
+        // Prefer `title` as the feed title; keep `text` as an alias only when
+        // it differs, since that usually means it was renamed in another reader
+        let title = o.title.clone().or_else(|| o.text.clone());
+        let alias = o.text.filter(|t| Some(t) != title.as_ref());
+
+        // Different URLs can make the same id (http:// and https://, or two
+        // feeds given the same name); an alias is the id, so it changes too
+        let id = unique_feed_id(store, &derive_feed_id(&o.url, alias.as_deref()));
+        let alias = alias.map(|_| id.clone());
+
+        let feed = Feed {
+            id,
+            url: o.url.clone(),
+            alias,
+            title,
+            added_at: Utc::now(),
+            last_fetched_at: None,
+            last_error: None,
+            group: o.group,
+            tags: Default::default(),
+            etag: None,
+            last_modified: None,
+            overrides: Default::default(),
+            hints: Default::default(),
+        };
+
+        store.add_feed(feed)?;
+        imported += 1;
+    }
+
+    println!("Imported {} feed(s) from {}", imported, path.display());
+
+    if !skipped.is_empty() {
+        println!("Skipped {} already subscribed feed(s):", skipped.len());
+        for url in skipped {
+            println!("- {}", url);
+        }
+    }
+
+    Ok(())
+}
+
+/// Export all feeds as OPML, to a file or stdout
+fn cmd_export(store: &dyn Storage, output: Option<&Path>) -> Result<()> {
+    let xml = opml::write_opml(store.feeds())?;
+
+    match output {
+        Some(path) => {
+            fs::write(path, xml)?;
+            eprintln!(
+                "Exported {} feed(s) to {}",
+                store.feeds().len(),
+                path.display()
+            );
+        }
+        None => print!("{}", xml),
+    }
+
+    Ok(())
+}
+
+/// Shortest wait between watch cycles, so feeds that keep failing (and so
+/// stay stale) aren't retried in a tight loop
+const MIN_WATCH_INTERVAL: Duration = Duration::minutes(1);
+
+/// Refresh the selected feeds whenever one goes stale, printing new items as
+/// they arrive, until interrupted.
+///
+/// Each cycle locks, loads, refreshes and saves the state, then lets go of
+/// it until the next feed is due, so other rsso commands can run in between.
+async fn cmd_watch(cfg: &Config, selector: &FeedSelector, view: &ViewOptions) -> Result<()> {
     let client = build_http_client()?;
 
-    // Refresh all feeds concurrently (only those that are stale)
-    let indices: Vec<usize> = (0..state.feeds.len()).collect();
-    refresh_feeds_concurrent(state, cfg, &client, indices).await?;
+    if view.format == OutputFormat::Text {
+        eprintln!("Watching for new items (Ctrl-C to stop)...");
+    }
 
-    // Build a feed label map once (feed_id -> label)
-    let label_map = build_feed_label_map(state);
+    loop {
+        let wait = match watch_cycle(cfg, &client, selector, view).await {
+            Ok(wait) => wait,
+            Err(err) => {
+                eprintln!("Refresh failed: {:#}", err);
+                MIN_WATCH_INTERVAL
+            }
+        };
 
-    // Build a vector of references (we used to clone items but this is faster)
-    let mut items: Vec<&Item> = state.items.iter().collect();
+        tokio::time::sleep(wait.to_std().unwrap_or_default()).await;
+    }
+}
 
-    sort_items_newest_first(&mut items);
+/// One watch cycle: refresh stale feeds, print what's new, and work out how
+/// long to wait until the next feed goes stale
+async fn watch_cycle(
+    cfg: &Config,
+    client: &Client,
+    selector: &FeedSelector,
+    view: &ViewOptions,
+) -> Result<Duration> {
+    let (lock, mut store) = storage::lock_and_open(cfg, LockMode::Exclusive)?;
+
+    let feeds = store.select_feeds(selector);
+    let new_items = refresh_feeds_concurrent(store.as_mut(), cfg, client, feeds).await?;
+    let mut follow_up = after_refresh(store.as_mut(), cfg, new_items)?;
+    store.save()?;
+    drop(lock);
+
+    // Oldest first, so the stream reads in the order things were published
+    follow_up.new_items.sort_by_key(|i| i.date());
+    if !follow_up.new_items.is_empty() {
+        print_items(&follow_up.new_items, store.feeds(), view, cfg)?;
+    }
+    run_follow_up(cfg, store.feeds(), follow_up, webhooks::WATCH_TRIES).await?;
 
-    for item in items.into_iter().take(limit) {
-        // Look up label by feed_id; fall back to the feed_id itself if missing
-        let feed_label = label_map
-            .get(&item.feed_id)
-            .map(|s| s.as_str())
-            .unwrap_or(&item.feed_id);
+    let now = Utc::now();
+    let next_due = store
+        .select_feeds(selector)
+        .iter()
+        // Failing feeds are retried whenever the others are next due
+        .filter(|f| f.last_error.is_none() && !f.overrides.disabled)
+        .filter_map(|f| Some(next_refresh_at(cfg, f, now)? - now))
+        .min()
+        .unwrap_or(refresh_duration(cfg.refresh_age_mins));
+
+    Ok(next_due.max(MIN_WATCH_INTERVAL))
+}
+
+/// Print the newest `limit` entries of the webhook delivery log
+fn cmd_deliveries(store: &dyn Storage, view: &ViewOptions) -> Result<()> {
+    let mut deliveries = store.deliveries()?;
+    deliveries.reverse();
+    deliveries.truncate(view.limit);
+
+    if view.format != OutputFormat::Text {
+        return print_records(&deliveries, view.format);
+    }
+
+    if deliveries.is_empty() {
+        println!("No webhook deliveries yet.");
+        return Ok(());
+    }
 
-        print_item_line(item, feed_label, cfg);
+    for d in deliveries {
+        let status = if d.delivered_at.is_some() {
+            "delivered".green()
+        } else if d.attempts >= webhooks::MAX_ATTEMPTS {
+            "failed".red()
+        } else {
+            "pending".yellow()
+        };
+        let when = d.last_attempt_at.unwrap_or(d.created_at);
+
+        let mut line = format!(
+            "{} | {} | {} | {}",
+            when.format("%d %b %y %H:%M"),
+            status,
+            d.webhook,
+            d.item_title
+        );
+        if let Some(err) = &d.last_error {
+            line.push_str(&format!(" | {} attempt(s): {}", d.attempts, err));
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Upgrade state.json to the current schema, or with `dry_run`, only list
+/// the migration steps and what each would change
+fn cmd_state_migrate(cfg: &Config, dry_run: bool) -> Result<()> {
+    let path = &cfg.state_path;
+    if !path.exists() {
+        println!("No state file at {}", path.display());
+        return Ok(());
+    }
+
+    let (state, report) = state::read_state_file(path)?;
+    if report.applied.is_empty() {
+        println!(
+            "{} is already at schema version {}",
+            path.display(),
+            SCHEMA_VERSION
+        );
+        return Ok(());
     }
 
+    println!(
+        "{} is at schema version {}; migrating to {}:",
+        path.display(),
+        report.from,
+        SCHEMA_VERSION
+    );
+    for step in &report.applied {
+        println!("  v{} -> v{}: {}", step.to - 1, step.to, step.description);
+        for change in &step.changes {
+            println!("    - {}", change);
+        }
+    }
+
+    if dry_run {
+        println!("Dry run: nothing was written.");
+        return Ok(());
+    }
+
+    let copy = state::keep_pre_migration_copy(path, report.from)?;
+    state::save_json_state(path, &state)?;
+    println!(
+        "Migrated {} (original kept as {})",
+        path.display(),
+        copy.display()
+    );
+    Ok(())
+}
+
+/// Default `rsso` behaviour: show recent items across all feeds, or only
+/// those matching `--tag`/`--group`. Returns the items the refresh found.
+async fn cmd_show_all(
+    store: &mut dyn Storage,
+    cfg: &Config,
+    selector: &FeedSelector,
+    view: &ViewOptions,
+) -> Result<Vec<Item>> {
+    if store.feeds().is_empty() {
+        if view.format != OutputFormat::Text {
+            print_records::<ItemRecord>(&[], view.format)?;
+            return Ok(Vec::new());
+        }
+        println!("No feeds subscribed. Use `rsso sub <url>` to add one.");
+        return Ok(Vec::new());
+    }
+
+    let selected = store.select_feeds(selector);
+    if selected.is_empty() {
+        bail!("No feeds match the given --tag/--group");
+    }
+
+    // Build a shared HTTP client
+    let client = build_http_client()?;
+
+    // Refresh selected feeds concurrently (only those that are stale)
+    let new_items = refresh_feeds_concurrent(store, cfg, &client, selected).await?;
+
+    // Re-select, since refreshing updated the feeds
+    let selected = store.select_feeds(selector);
+
+    let feed_ids = listed_feed_ids(store, selector);
+    let query = view.item_query(store, feed_ids, Some(view.limit))?;
+    let items = listed_items(store, cfg, query)?;
+
+    print_items(&items, store.feeds(), view, cfg)?;
+    record_view(store, view, selector)?;
+
     // After printing items, show a warning if any feeds had errors
-    let failing: Vec<_> = state
-        .feeds
-        .iter()
-        .filter(|f| f.last_error.is_some())
-        .collect();
+    let failing: Vec<_> = selected.iter().filter(|f| f.last_error.is_some()).collect();
 
     if !failing.is_empty() {
         eprintln!();
         eprintln!("Warning: {} feed(s) had errors:", failing.len());
         for f in failing {
-            let label = f
-                .alias
-                .clone()
-                .or_else(|| f.title.clone())
-                .unwrap_or_else(|| f.id.clone());
             eprintln!(
                 "- {} ({})",
-                label,
+                feed_label(f),
                 f.last_error.as_deref().unwrap_or("unknown error")
             );
         }
         eprintln!("Run `rsso list` for more details.");
     }
 
-    Ok(())
+    Ok(new_items)
 }
 
-/// Show recent items for a single feed
-async fn cmd_show_feed(state: &mut State, cfg: &Config, key: &str, limit: usize) -> Result<()> {
-    // Find index of the matching feed using alias OR title OR id OR url
-    let feed_index = match state.find_feed_index(key) {
-        Some(i) => i,
+/// Show recent items for a single feed, returning the items the refresh
+/// found
+async fn cmd_show_feed(
+    store: &mut dyn Storage,
+    cfg: &Config,
+    key: &str,
+    selector: &FeedSelector,
+    view: &ViewOptions,
+) -> Result<Vec<Item>> {
+    // Find the matching feed using alias OR title OR id OR url
+    let feed = match store.find_selected_feed(key, selector) {
+        Some(f) => f,
         None => {
             bail!("No matching feed for '{}'", key);
         }
     };
+    let feed_id = feed.id.clone();
+    let limit = view.limit_for(&feed);
 
     let client = build_http_client()?;
 
     // Refresh that single feed if needed
-    refresh_feed_if_needed(state, feed_index, cfg, &client).await?;
+    let new_items = refresh_feed_if_needed(store, feed, cfg, &client).await?;
 
-    let feed = &state.feeds[feed_index];
-    let feed_id = feed.id.clone();
+    // Only the latest `limit` items for this feed, newest first
+    let query = view.item_query(store, vec![feed_id], Some(limit))?;
+    let items = listed_items(store, cfg, query)?;
 
-    // Get feed label from alias, title or id
-    let feed_label = feed
-        .alias
-        .as_deref()
-        .or(feed.title.as_deref())
-        .unwrap_or(&feed.id);
+    print_items(&items, store.feeds(), view, cfg)?;
 
-    // Collect references to items only for this feed
-    let mut items: Vec<&Item> = state
-        .items
-        .iter()
-        .filter(|i| i.feed_id == feed_id)
-        .collect();
-
-    // Sort newest first
-    sort_items_newest_first(&mut items);
-
-    // Print only the latest `limit` items
-    for item in items.into_iter().take(limit) {
-        print_item_line(item, feed_label, cfg);
-    }
-
-    Ok(())
+    Ok(new_items)
 }
 
-/// Refresh all feeds, or a selected subset
-async fn cmd_refresh(state: &mut State, cfg: &Config, keys: &[String]) -> Result<()> {
-    if state.feeds.is_empty() {
+/// Refresh all feeds, or a selected subset, returning the items seen for the
+/// first time
+async fn cmd_refresh(
+    store: &mut dyn Storage,
+    cfg: &Config,
+    keys: &[String],
+    selector: &FeedSelector,
+) -> Result<Vec<Item>> {
+    if store.feeds().is_empty() {
         println!("No feeds subscribed.");
-        return Ok(());
+        return Ok(Vec::new());
     }
 
     let client = build_http_client()?;
+    let mut new_items = Vec::new();
 
     if keys.is_empty() {
-        // No specific keys: refresh all feeds concurrently
-        let indices: Vec<usize> = (0..state.feeds.len()).collect();
-        refresh_feeds_concurrent(state, cfg, &client, indices).await?;
-        println!("Refreshed all feeds.");
+        // No specific keys: refresh all (selected) feeds concurrently
+        let feeds = store.select_feeds(selector);
+        let count = feeds.len();
+        new_items = refresh_feeds_concurrent(store, cfg, &client, feeds).await?;
+        if selector.is_empty() {
+            println!("Refreshed all feeds.");
+        } else {
+            println!("Refreshed {} selected feed(s).", count);
+        }
     } else {
         // Keys were provided: refresh only selected feeds (sequentially is fine)
         for key in keys {
-            match state.find_feed_index(key) {
-                Some(i) => {
-                    refresh_feed_if_needed(state, i, cfg, &client).await?;
+            match store.find_selected_feed(key, selector) {
+                Some(feed) if feed.overrides.disabled => {
+                    eprintln!("Skipped {} (disabled)", key);
+                }
+                Some(feed) => {
+                    new_items.extend(refresh_feed_if_needed(store, feed, cfg, &client).await?);
                     println!("Refreshed {}", key);
                 }
                 None => {
@@ -546,5 +2044,758 @@ async fn cmd_refresh(state: &mut State, cfg: &Config, keys: &[String]) -> Result
         }
     }
 
-    Ok(())
+    Ok(new_items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::filters::{Filter, FilterField, Pattern};
+    use crate::sqlite::SqliteStore;
+    use crate::state::{State, TestDir};
+    use crate::storage::MemoryStore;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn test_feed(id: &str) -> Feed {
+        Feed {
+            id: id.to_string(),
+            url: format!("https://example.com/{id}.xml"),
+            alias: None,
+            title: Some(id.to_string()),
+            added_at: at("2024-05-01T00:00:00Z"),
+            last_fetched_at: None,
+            last_error: None,
+            group: None,
+            tags: Default::default(),
+            etag: None,
+            last_modified: None,
+            overrides: Default::default(),
+            hints: Default::default(),
+        }
+    }
+
+    fn test_item(feed: &Feed, key: &str, published: &str) -> Item {
+        Item {
+            id: state::item_id(&feed.url, key),
+            feed_id: feed.id.clone(),
+            guid: Some(key.to_string()),
+            title: format!("Post {key}"),
+            link: format!("https://example.com/{key}"),
+            published_at: Some(at(published)),
+            updated_at: None,
+            summary: None,
+            content: None,
+            author: None,
+            first_seen_at: at(published),
+            read: false,
+        }
+    }
+
+    fn test_store(feeds: Vec<Feed>, items: Vec<Item>) -> MemoryStore {
+        MemoryStore::from_state(State {
+            feeds,
+            items,
+            ..State::default()
+        })
+    }
+
+    /// A stand-in webhook endpoint on localhost, answering one request per
+    /// status given. Returns its URL, and a handle that yields each request's
+    /// head and body.
+    fn webhook_server(statuses: Vec<u16>) -> (String, thread::JoinHandle<Vec<(String, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in statuses {
+                let (mut conn, _) = listener.accept().unwrap();
+                let mut buf = Vec::new();
+                let mut chunk = [0; 4096];
+                let (head, body_start) = loop {
+                    let n = conn.read(&mut chunk).unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break (String::from_utf8_lossy(&buf[..end]).to_lowercase(), end + 4);
+                    }
+                };
+                let length: usize = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("content-length:"))
+                    .map(|v| v.trim().parse().unwrap())
+                    .unwrap_or(0);
+                while buf.len() < body_start + length {
+                    let n = conn.read(&mut chunk).unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                }
+                let body = String::from_utf8_lossy(&buf[body_start..]).to_string();
+
+                write!(
+                    conn,
+                    "HTTP/1.1 {status} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                )
+                .unwrap();
+                requests.push((head, body));
+            }
+            requests
+        });
+
+        (url, handle)
+    }
+
+    fn test_webhook(url: &str) -> Webhook {
+        Webhook {
+            url: url.to_string(),
+            feed: None,
+            tag: None,
+            template: None,
+            content_type: "application/json".to_string(),
+        }
+    }
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn when_at(s: &str, until: bool, now: DateTime<Utc>) -> DateTime<Utc> {
+        match parse_when(s, until, now).unwrap() {
+            When::At(t) => t,
+            When::LastRun => panic!("expected a time for '{s}'"),
+        }
+    }
+
+    #[test]
+    fn parse_span_units() {
+        assert_eq!(parse_span("30m").unwrap(), Duration::minutes(30));
+        assert_eq!(parse_span("12h").unwrap(), Duration::hours(12));
+        assert_eq!(parse_span("2d").unwrap(), Duration::days(2));
+        assert_eq!(parse_span("1w").unwrap(), Duration::weeks(1));
+        assert!(parse_span("3x").is_err());
+        assert!(parse_span("d").is_err());
+    }
+
+    #[test]
+    fn parse_span_overflow_is_an_error() {
+        assert!(parse_span("99999999999999d").is_err());
+        assert!(parse_span("99999999999999999999m").is_err());
+    }
+
+    #[test]
+    fn parse_refresh_checks_range() {
+        assert_eq!(parse_refresh("15").unwrap(), 15);
+        assert_eq!(parse_refresh("2h").unwrap(), 120);
+        assert_eq!(parse_refresh("1d").unwrap(), 24 * 60);
+        assert!(parse_refresh("99999999999999999").is_err());
+        assert!(parse_refresh("99999999999999d").is_err());
+        assert!(parse_refresh("400d").is_err());
+    }
+
+    #[test]
+    fn parse_when_spans_count_back_from_now() {
+        let now = at("2024-05-10T12:00:00Z");
+        assert_eq!(when_at("2d", false, now), at("2024-05-08T12:00:00Z"));
+        assert_eq!(when_at("90m", true, now), at("2024-05-10T10:30:00Z"));
+    }
+
+    #[test]
+    fn parse_when_dates() {
+        let now = at("2024-05-10T12:00:00Z");
+        assert_eq!(
+            when_at("2024-05-01", false, now),
+            at("2024-05-01T00:00:00Z")
+        );
+        // --until includes the whole day
+        assert_eq!(when_at("2024-05-01", true, now), at("2024-05-02T00:00:00Z"));
+        assert_eq!(
+            when_at("2024-05-01T08:30:00+02:00", false, now),
+            at("2024-05-01T06:30:00Z")
+        );
+    }
+
+    #[test]
+    fn parse_when_last_run() {
+        let now = at("2024-05-10T12:00:00Z");
+        assert!(matches!(
+            parse_when("last-run", false, now).unwrap(),
+            When::LastRun
+        ));
+        assert!(parse_when("yesterday", false, now).is_err());
+    }
+
+    #[test]
+    fn parse_when_overflow_is_an_error() {
+        let now = at("2024-05-10T12:00:00Z");
+        assert!(parse_when("99999999999999d", false, now).is_err());
+        assert!(parse_when("100000000w", false, now).is_err());
+    }
+
+    /// One run's deliveries: take on what's pending, post it and record how
+    /// it went
+    async fn deliver(store: &mut dyn Storage, cfg: &Config, new_items: &[Item], tries: u32) {
+        let pending = queue_deliveries(store, cfg, new_items, Utc::now()).unwrap();
+        let results = post_deliveries(&Client::new(), pending, tries).await;
+        record_deliveries(store, results, Utc::now()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn webhook_failures_are_logged_and_retried_on_the_next_run() {
+        let feed = test_feed("news");
+        let item = test_item(&feed, "a", "2024-05-01T10:00:00Z");
+        let mut store = test_store(vec![feed], vec![item.clone()]);
+
+        let (url, server) = webhook_server(vec![500, 200]);
+        let mut cfg = test_config(Path::new("."));
+        cfg.webhooks.push(test_webhook(&url));
+
+        // One request inline; the 500 leaves the delivery pending
+        deliver(
+            &mut store,
+            &cfg,
+            std::slice::from_ref(&item),
+            webhooks::INLINE_TRIES,
+        )
+        .await;
+        let log = store.deliveries().unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].attempts, 1);
+        assert!(log[0].delivered_at.is_none());
+        assert!(log[0].last_error.as_deref().unwrap().contains("500"));
+
+        // The next run retries it without logging the item again
+        deliver(&mut store, &cfg, &[], webhooks::INLINE_TRIES).await;
+        let log = store.deliveries().unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].attempts, 2);
+        assert!(log[0].delivered_at.is_some());
+        assert!(log[0].last_error.is_none());
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        for (head, body) in &requests {
+            assert!(head.starts_with("post /hook "));
+            assert!(head.contains("content-type: application/json"));
+            let json: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(json["id"], item.id);
+            assert_eq!(json["title"], "Post a");
+            assert_eq!(json["feed"], "news");
+        }
+    }
+
+    #[tokio::test]
+    async fn deliveries_to_removed_webhooks_are_given_up() {
+        let feed = test_feed("news");
+        let item = test_item(&feed, "a", "2024-05-01T10:00:00Z");
+        let mut store = test_store(vec![feed], vec![item.clone()]);
+        store
+            .save_delivery(&Delivery::new("http://127.0.0.1:9/gone", &item, Utc::now()))
+            .unwrap();
+
+        deliver(&mut store, &test_config(Path::new(".")), &[], 1).await;
+
+        let log = store.deliveries().unwrap();
+        assert_eq!(log[0].attempts, webhooks::MAX_ATTEMPTS);
+        assert!(log[0].delivered_at.is_none());
+        assert!(log[0].last_error.is_some());
+    }
+
+    #[test]
+    fn deliveries_taken_on_by_another_run_are_left_alone() {
+        let feed = test_feed("news");
+        let item = test_item(&feed, "a", "2024-05-01T10:00:00Z");
+        let mut store = test_store(vec![feed], vec![item.clone()]);
+        let mut cfg = test_config(Path::new("."));
+        cfg.webhooks.push(test_webhook("http://127.0.0.1:9/hook"));
+        let now = at("2024-05-01T12:00:00Z");
+
+        let first = queue_deliveries(&mut store, &cfg, std::slice::from_ref(&item), now).unwrap();
+        assert_eq!(first.len(), 1);
+        let log = store.deliveries().unwrap();
+        assert_eq!(log[0].last_error.as_deref(), Some(webhooks::IN_FLIGHT));
+
+        // A run alongside doesn't send it again
+        let second = queue_deliveries(&mut store, &cfg, &[], now).unwrap();
+        assert!(second.is_empty());
+
+        // Unless the first run never came back to record it
+        let later = now + Duration::minutes(webhooks::IN_FLIGHT_MINS + 1);
+        let retried = queue_deliveries(&mut store, &cfg, &[], later).unwrap();
+        assert_eq!(retried.len(), 1);
+        assert_eq!(retried[0].delivery.attempts, 0);
+    }
+
+    fn fetched(items: Vec<Item>) -> FetchResult {
+        Ok(FetchOutcome::Updated(crate::fetch::FetchedFeed {
+            title: None,
+            items,
+            etag: None,
+            last_modified: None,
+            hints: Default::default(),
+        }))
+    }
+
+    #[test]
+    fn refresh_merges_fetched_items_and_trims_history() {
+        let mut feed = test_feed("news");
+        feed.last_fetched_at = Some(at("2024-05-02T00:00:00Z"));
+        let other = test_feed("other");
+
+        let oldest = test_item(&feed, "b", "2024-04-30T00:00:00Z");
+        let mut known = test_item(&feed, "a", "2024-05-01T00:00:00Z");
+        known.read = true;
+        let other_item = test_item(&other, "x", "2024-01-01T00:00:00Z");
+        let mut store = test_store(
+            vec![feed.clone(), other],
+            vec![oldest.clone(), known.clone(), other_item.clone()],
+        );
+
+        let mut refetched = test_item(&feed, "a", "2024-05-01T00:00:00Z");
+        refetched.title = "Post a (updated)".to_string();
+        refetched.first_seen_at = at("2024-05-05T00:00:00Z");
+        let c = test_item(&feed, "c", "2024-05-03T00:00:00Z");
+        let d = test_item(&feed, "d", "2024-05-04T00:00:00Z");
+
+        let mut cfg = test_config(Path::new("."));
+        cfg.max_history_per_feed = 3;
+        let now = at("2024-05-05T00:00:00Z");
+        let new_items = apply_fetch_result(
+            &mut store,
+            &cfg,
+            feed,
+            fetched(vec![refetched, c.clone(), d.clone()]),
+            now,
+        )
+        .unwrap();
+
+        let new_ids: Vec<&str> = new_items.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(new_ids, [c.id.as_str(), d.id.as_str()]);
+
+        // The known item takes the fetched text but keeps what we know of it
+        let merged = store.find_item(&known.id).unwrap();
+        assert_eq!(merged.title, "Post a (updated)");
+        assert!(merged.read);
+        assert_eq!(merged.first_seen_at, known.first_seen_at);
+
+        // Only the oldest item of the refreshed feed is trimmed
+        assert!(store.find_item(&oldest.id).is_err());
+        assert!(store.find_item(&other_item.id).is_ok());
+        assert_eq!(store.state().items.len(), 4);
+        assert_eq!(store.feeds()[0].last_fetched_at, Some(now));
+    }
+
+    #[test]
+    fn refresh_waits_for_the_longest_hint() {
+        let cfg = test_config(Path::new("."));
+        let mut feed = test_feed("news");
+        let now = at("2024-05-10T10:30:00Z");
+        assert_eq!(next_refresh_at(&cfg, &feed, now), None);
+
+        feed.last_fetched_at = Some(at("2024-05-10T10:00:00Z"));
+        assert_eq!(
+            next_refresh_at(&cfg, &feed, now),
+            Some(at("2024-05-10T11:00:00Z"))
+        );
+
+        feed.hints.ttl_mins = Some(120);
+        feed.hints.update_interval_mins = Some(180);
+        assert_eq!(
+            next_refresh_at(&cfg, &feed, now),
+            Some(at("2024-05-10T13:00:00Z"))
+        );
+        feed.hints.fresh_until = Some(at("2024-05-10T14:15:00Z"));
+        assert_eq!(
+            next_refresh_at(&cfg, &feed, now),
+            Some(at("2024-05-10T14:15:00Z"))
+        );
+
+        // Hints never hold a feed off for longer than a week
+        feed.hints.ttl_mins = Some(60 * 24 * 365);
+        assert_eq!(
+            next_refresh_at(&cfg, &feed, now),
+            Some(at("2024-05-17T10:00:00Z"))
+        );
+
+        let mut cfg = cfg;
+        cfg.ignore_feed_hints = true;
+        assert_eq!(
+            next_refresh_at(&cfg, &feed, now),
+            Some(at("2024-05-10T11:00:00Z"))
+        );
+        // Overdue feeds are due now
+        let later = at("2024-05-11T00:00:00Z");
+        assert_eq!(next_refresh_at(&cfg, &feed, later), Some(later));
+    }
+
+    #[test]
+    fn refresh_intervals_are_capped() {
+        let mut cfg = test_config(Path::new("."));
+        cfg.refresh_age_mins = u64::MAX;
+        let mut feed = test_feed("news");
+        let year = Duration::minutes(MAX_REFRESH_MINS as i64);
+        assert_eq!(refresh_interval(&cfg, &feed), year);
+        assert_eq!(refresh_duration(cfg.refresh_age_mins), year);
+
+        cfg.refresh_age_mins = 60;
+        feed.overrides.refresh_mins = Some(i64::MAX as u64 + 1);
+        assert_eq!(refresh_interval(&cfg, &feed), year);
+    }
+
+    #[test]
+    fn refresh_skips_hours_and_days_across_midnight() {
+        let cfg = test_config(Path::new("."));
+        let mut feed = test_feed("news");
+        // A Friday evening
+        feed.last_fetched_at = Some(at("2024-05-10T21:30:00Z"));
+        let now = at("2024-05-10T21:45:00Z");
+
+        feed.hints.skip_hours = [22, 23, 0].into();
+        assert_eq!(
+            next_refresh_at(&cfg, &feed, now),
+            Some(at("2024-05-11T01:00:00Z"))
+        );
+
+        // Saturday (5) is skipped too, so it waits for Sunday
+        feed.hints.skip_days = [5].into();
+        assert_eq!(
+            next_refresh_at(&cfg, &feed, now),
+            Some(at("2024-05-12T01:00:00Z"))
+        );
+
+        // Skipping every day gives up after a week rather than looping
+        feed.hints.skip_days = (0..7).collect();
+        assert_eq!(
+            next_refresh_at(&cfg, &feed, now),
+            Some(at("2024-05-17T22:00:00Z"))
+        );
+        assert!(!needs_refresh(&cfg, &feed, now));
+    }
+
+    #[test]
+    fn first_fetch_is_not_news() {
+        let feed = test_feed("news");
+        let mut store = test_store(vec![feed.clone()], Vec::new());
+        let item = test_item(&feed, "a", "2024-05-01T00:00:00Z");
+
+        let cfg = test_config(Path::new("."));
+        let new_items =
+            apply_fetch_result(&mut store, &cfg, feed, fetched(vec![item]), Utc::now()).unwrap();
+        assert!(new_items.is_empty());
+        assert_eq!(store.state().items.len(), 1);
+    }
+
+    #[test]
+    fn mark_read_counts_each_item_once() {
+        let feed = test_feed("news");
+        let other = test_feed("other");
+        let a = test_item(&feed, "a", "2024-05-01T00:00:00Z");
+        let b = test_item(&feed, "b", "2024-05-02T00:00:00Z");
+        let x = test_item(&other, "x", "2024-05-02T00:00:00Z");
+        let mut store = test_store(vec![feed, other], vec![a.clone(), b.clone(), x.clone()]);
+
+        let ids = [a.id.clone(), a.id.clone(), "missing".to_string()];
+        assert_eq!(store.mark_read(&ids).unwrap(), 1);
+        assert_eq!(store.mark_read(&ids).unwrap(), 0);
+
+        cmd_mark_read(&mut store, Some("news"), &FeedSelector::default()).unwrap();
+        assert!(store.find_item(&b.id).unwrap().read);
+        assert!(!store.find_item(&x.id).unwrap().read);
+    }
+
+    #[test]
+    fn find_item_takes_unambiguous_prefixes() {
+        let feed = test_feed("news");
+        let mut a = test_item(&feed, "a", "2024-05-01T00:00:00Z");
+        a.id = "abc1230000000000".to_string();
+        let mut b = test_item(&feed, "b", "2024-05-02T00:00:00Z");
+        b.id = "abc4560000000000".to_string();
+        let mut store = test_store(vec![feed], vec![a.clone(), b.clone()]);
+
+        assert_eq!(store.find_item("abc1").unwrap().id, a.id);
+        assert_eq!(store.find_item("ABC4").unwrap().id, b.id);
+        assert_eq!(store.find_item(&b.link).unwrap().id, b.id);
+
+        let err = store.find_item("abc").unwrap_err();
+        assert!(err.to_string().contains("ambiguous (2 matches)"));
+        assert!(store.find_item("fff").is_err());
+        assert!(store.find_item("").is_err());
+
+        // `rsso read` reports the ambiguity and marks nothing
+        assert!(cmd_read(&mut store, &["abc".to_string()]).is_err());
+        assert!(!store.find_item(&a.id).unwrap().read);
+        cmd_read(&mut store, &["abc1".to_string()]).unwrap();
+        assert!(store.find_item(&a.id).unwrap().read);
+    }
+
+    #[test]
+    fn import_skips_only_feeds_already_subscribed() {
+        let mut existing = test_feed("news");
+        existing.url = "https://news.example.com/rss".to_string();
+        let mut store = test_store(vec![existing], Vec::new());
+
+        let dir = TestDir::new("import");
+        let path = dir.join("feeds.opml");
+        fs::write(
+            &path,
+            r#"<?xml version="1.0"?>
+<opml version="2.0"><body>
+  <outline text="News" xmlUrl="https://news.example.com/rss"/>
+  <outline text="X" xmlUrl="http://x.example.com/feed"/>
+  <outline text="X" xmlUrl="https://x.example.com/feed"/>
+  <outline text="Blog" title="A blog" xmlUrl="https://a.example.com/feed"/>
+  <outline text="Blog" title="Another blog" xmlUrl="https://b.example.com/feed"/>
+  <outline text="blog" title="A third blog" xmlUrl="https://c.example.com/feed"/>
+</body></opml>"#,
+        )
+        .unwrap();
+
+        cmd_import(&mut store, &path).unwrap();
+
+        let feeds: Vec<(&str, &str)> = store
+            .feeds()
+            .iter()
+            .map(|f| (f.id.as_str(), f.url.as_str()))
+            .collect();
+        assert_eq!(
+            feeds,
+            [
+                ("news", "https://news.example.com/rss"),
+                ("x.example.com-feed", "http://x.example.com/feed"),
+                ("x.example.com-feed-2", "https://x.example.com/feed"),
+                ("Blog", "https://a.example.com/feed"),
+                ("Blog-2", "https://b.example.com/feed"),
+                ("blog-3", "https://c.example.com/feed"),
+            ]
+        );
+        assert_eq!(store.feeds()[4].alias.as_deref(), Some("Blog-2"));
+    }
+
+    fn search_for(store: &dyn Storage, cfg: &Config, query: &str) -> Vec<String> {
+        let query = SearchQuery {
+            terms: search::parse_query(query).unwrap().terms,
+            limit: Some(1),
+            ..SearchQuery::default()
+        };
+        searched_items(store, cfg, query)
+            .unwrap()
+            .into_iter()
+            .map(|i| i.title)
+            .collect()
+    }
+
+    #[test]
+    fn search_ignores_accents_in_every_store() {
+        let feed = test_feed("news");
+        let mut item = test_item(&feed, "a", "2024-05-01T00:00:00Z");
+        item.title = "Café Society".to_string();
+
+        let dir = TestDir::new("search");
+        let cfg = test_config(&dir);
+        let mut sqlite = SqliteStore::open(&cfg, LockMode::Exclusive).unwrap();
+        sqlite.add_feed(feed.clone()).unwrap();
+        sqlite.upsert_items("news", vec![item.clone()]).unwrap();
+        let memory = test_store(vec![feed], vec![item]);
+
+        for store in [&memory as &dyn Storage, &sqlite] {
+            assert_eq!(search_for(store, &cfg, "cafe"), ["Café Society"]);
+            assert_eq!(search_for(store, &cfg, "CAFÉ"), ["Café Society"]);
+        }
+    }
+
+    #[test]
+    fn search_leaves_out_hidden_items_before_the_limit() {
+        let feed = test_feed("news");
+        let mut sponsored = test_item(&feed, "a", "2024-05-02T00:00:00Z");
+        sponsored.title = "Sponsored: rust hosting".to_string();
+        let mut post = test_item(&feed, "b", "2024-05-01T00:00:00Z");
+        post.title = "Rust hosting, compared at length over many words".to_string();
+        let store = test_store(vec![feed], vec![sponsored, post]);
+
+        let mut cfg = test_config(Path::new("/nonexistent"));
+        assert_eq!(
+            search_for(&store, &cfg, "rust"),
+            ["Sponsored: rust hosting"]
+        );
+
+        cfg.filters.push(Filter {
+            pattern: Pattern::Keyword(vec!["sponsored".to_string()]),
+            feed: None,
+            tag: None,
+            fields: vec![FilterField::Title],
+            action: FilterAction::Hide,
+        });
+        assert_eq!(
+            search_for(&store, &cfg, "rust"),
+            ["Rust hosting, compared at length over many words"]
+        );
+    }
+
+    #[test]
+    fn listings_page_past_hidden_items_in_every_store() {
+        let feed = test_feed("news");
+        let items: Vec<Item> = (1..=7)
+            .map(|day| {
+                let mut item = test_item(
+                    &feed,
+                    &day.to_string(),
+                    &format!("2024-05-0{day}T00:00:00Z"),
+                );
+                if day % 3 != 1 {
+                    item.title = format!("Sponsored {day}");
+                }
+                item
+            })
+            .collect();
+
+        let dir = TestDir::new("paging");
+        let mut cfg = test_config(&dir);
+        let mut sqlite = SqliteStore::open(&cfg, LockMode::Exclusive).unwrap();
+        sqlite.add_feed(feed.clone()).unwrap();
+        sqlite.upsert_items("news", items.clone()).unwrap();
+        let memory = test_store(vec![feed], items);
+
+        cfg.filters.push(Filter {
+            pattern: Pattern::Keyword(vec!["sponsored".to_string()]),
+            feed: None,
+            tag: None,
+            fields: vec![FilterField::Title],
+            action: FilterAction::Hide,
+        });
+        for store in [&memory as &dyn Storage, &sqlite] {
+            let listed = |limit| {
+                let query = ItemQuery {
+                    limit,
+                    ..ItemQuery::default()
+                };
+                let items = listed_items(store, &cfg, query).unwrap();
+                items.into_iter().map(|i| i.title).collect::<Vec<_>>()
+            };
+            assert_eq!(listed(Some(2)), ["Post 7", "Post 4"]);
+            assert_eq!(listed(Some(5)), ["Post 7", "Post 4", "Post 1"]);
+            assert_eq!(listed(None), ["Post 7", "Post 4", "Post 1"]);
+            assert!(listed(Some(0)).is_empty());
+        }
+    }
+
+    #[test]
+    fn open_since_last_run_refers_to_the_last_listing() {
+        let feed = test_feed("news");
+        let old = test_item(&feed, "a", "2024-05-01T00:00:00Z");
+        let new = test_item(&feed, "b", "2024-05-03T00:00:00Z");
+
+        let dir = TestDir::new("open");
+        let mut cfg = test_config(&dir);
+        cfg.opener = Some("true".to_string());
+        let mut sqlite = SqliteStore::open(&cfg, LockMode::Exclusive).unwrap();
+        sqlite.add_feed(feed.clone()).unwrap();
+        sqlite
+            .upsert_items("news", vec![old.clone(), new.clone()])
+            .unwrap();
+        let mut memory = test_store(vec![feed], vec![old.clone(), new.clone()]);
+
+        let view = ViewOptions {
+            limit: 20,
+            requested_limit: None,
+            unread_only: false,
+            since: Some(When::LastRun),
+            until: None,
+            format: OutputFormat::Text,
+            template: Template::default(),
+        };
+        for store in [&mut memory as &mut dyn Storage, &mut sqlite] {
+            store
+                .set_last_viewed_at(at("2024-05-02T00:00:00Z"))
+                .unwrap();
+            // `rsso --since last-run` listed the new item, and moved the mark
+            store
+                .set_last_viewed_at(at("2024-05-04T00:00:00Z"))
+                .unwrap();
+
+            let keys = ["@1".to_string()];
+            // `rsso feed news` doesn't move the mark, so neither does its open
+            let err = cmd_open(
+                store,
+                &cfg,
+                &keys,
+                Some("news"),
+                &FeedSelector::default(),
+                &view,
+            )
+            .unwrap_err();
+            assert!(err.to_string().contains("listing has 0"));
+
+            let to_open =
+                cmd_open(store, &cfg, &keys, None, &FeedSelector::default(), &view).unwrap();
+            let ids: Vec<&str> = to_open.iter().map(|i| i.id.as_str()).collect();
+            assert_eq!(ids, [new.id.as_str()]);
+        }
+    }
+
+    #[test]
+    fn open_takes_ids_and_positions_marked_with_at() {
+        let feed = test_feed("news");
+        let mut numeric = test_item(&feed, "a", "2024-05-01T00:00:00Z");
+        numeric.id = "123e4567".to_string();
+        let newest = test_item(&feed, "b", "2024-05-03T00:00:00Z");
+        let store = test_store(vec![feed], vec![numeric.clone(), newest.clone()]);
+
+        let cfg = test_config(Path::new("."));
+        let view = ViewOptions {
+            limit: 1,
+            requested_limit: None,
+            unread_only: false,
+            since: None,
+            until: None,
+            format: OutputFormat::Text,
+            template: Template::default(),
+        };
+        let open = |keys: &[&str]| {
+            let keys: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+            cmd_open(&store, &cfg, &keys, None, &FeedSelector::default(), &view)
+        };
+
+        // Short all-digit keys are id prefixes like any other
+        assert_eq!(open(&["123"]).unwrap(), [numeric.clone()]);
+        assert_eq!(open(&["@2", "@1"]).unwrap(), [numeric, newest.clone()]);
+        assert_eq!(open(&[]).unwrap(), [newest]);
+
+        let err = open(&["3"]).unwrap_err().to_string();
+        assert!(err.contains("use @3"), "{err}");
+        assert!(
+            open(&["@3"])
+                .unwrap_err()
+                .to_string()
+                .contains("listing has 2")
+        );
+        assert!(open(&["@0"]).is_err());
+        assert!(open(&["@x"]).is_err());
+    }
+
+    #[test]
+    fn only_listings_of_everything_new_move_the_last_run_mark() {
+        let mut view = ViewOptions {
+            limit: 20,
+            requested_limit: None,
+            unread_only: true,
+            since: Some(When::LastRun),
+            until: None,
+            format: OutputFormat::Text,
+            template: Template::default(),
+        };
+        let everything = FeedSelector::default();
+        assert!(shows_all_new(&view, &everything));
+
+        let work = FeedSelector {
+            tags: vec!["work".to_string()],
+            group: None,
+        };
+        assert!(!shows_all_new(&view, &work));
+
+        view.since = Some(When::At(at("2024-05-01T00:00:00Z")));
+        assert!(!shows_all_new(&view, &everything));
+        view.since = None;
+        view.until = Some(at("2024-05-01T00:00:00Z"));
+        assert!(!shows_all_new(&view, &everything));
+    }
 }
@@ -1,9 +1,16 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use dirs::{config_dir, data_dir};
+use regex::Regex;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
+use crate::filters::{Filter, FilterAction, FilterField, Pattern};
+use crate::search::words;
+use crate::state::{Feed, normalize_tag};
+use crate::template::Template;
+
 /// Shape of config.toml on disk
 ///
 /// Example:
@@ -11,6 +18,27 @@ use std::path::PathBuf;
 /// refresh_age_mins = 60
 /// new_line_between_items = false
 /// state_file = "/some/custom/path.json"
+/// item_template = "{date:%Y-%m-%d} {feed}: {title}"
+/// opener = "firefox --new-tab"
+/// storage = "sqlite"
+/// database_file = "/some/custom/rsso.db"
+/// lock_timeout_secs = 30
+/// ignore_feed_hints = false
+/// on_new_item = "notify-send \"$RSSO_FEED\" \"$RSSO_ITEM_TITLE\""
+///
+/// [feeds.rust]
+/// on_new_item = "~/bin/archive-item"
+///
+/// [[webhooks]]
+/// url = "http://localhost:9000/rsso"
+/// tag = "news"
+/// template = '{{"text": "{feed}: {title} {link}"}}'
+///
+/// [[filters]]
+/// keyword = "sponsored"
+/// tag = "news"
+/// fields = ["title", "summary"]
+/// action = "hide"
 #[derive(Debug, Deserialize)]
 pub struct RawConfig {
     pub default_limit: Option<usize>,
@@ -18,6 +46,109 @@ pub struct RawConfig {
     pub new_line_between_items: Option<bool>,
     pub max_history_per_feed: Option<usize>,
     pub state_file: Option<String>,
+    pub item_template: Option<String>,
+    pub opener: Option<String>,
+    pub storage: Option<StorageKind>,
+    pub database_file: Option<String>,
+    pub lock_timeout_secs: Option<u64>,
+    pub ignore_feed_hints: Option<bool>,
+    pub on_new_item: Option<String>,
+    /// Settings for individual feeds, keyed by alias (or title, id or url)
+    pub feeds: Option<HashMap<String, FeedSettings>>,
+    pub webhooks: Option<Vec<RawWebhook>>,
+    pub filters: Option<Vec<RawFilter>>,
+}
+
+/// A `[[webhooks]]` entry in config.toml
+#[derive(Debug, Deserialize)]
+pub struct RawWebhook {
+    pub url: String,
+    pub feed: Option<String>,
+    pub tag: Option<String>,
+    pub template: Option<String>,
+    pub content_type: Option<String>,
+}
+
+/// A `[[filters]]` entry in config.toml
+#[derive(Debug, Deserialize)]
+pub struct RawFilter {
+    pub keyword: Option<String>,
+    pub regex: Option<String>,
+    pub feed: Option<String>,
+    pub tag: Option<String>,
+    pub fields: Option<Vec<FilterField>>,
+    pub action: Option<FilterAction>,
+}
+
+impl RawFilter {
+    /// Check the entry and turn it into a rule
+    pub fn resolve(self) -> Result<Filter> {
+        let pattern = match (self.keyword, self.regex) {
+            (Some(keyword), None) => {
+                let keyword_words = words(&keyword);
+                if keyword_words.is_empty() {
+                    bail!("Filter keyword '{}' has no words to match", keyword);
+                }
+                Pattern::Keyword(keyword_words)
+            }
+            (None, Some(re)) => Pattern::Regex(
+                Regex::new(&re).with_context(|| format!("Invalid filter regex '{}'", re))?,
+            ),
+            _ => bail!("Each [[filters]] entry needs either keyword or regex"),
+        };
+
+        Ok(Filter {
+            pattern,
+            feed: self.feed,
+            tag: self.tag,
+            fields: self
+                .fields
+                .unwrap_or_else(|| vec![FilterField::Title, FilterField::Summary]),
+            action: self.action.unwrap_or(FilterAction::Hide),
+        })
+    }
+}
+
+/// Where to POST new items
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub url: String,
+    /// Only items from this feed (alias/title/id/url)
+    pub feed: Option<String>,
+    /// Only items from feeds with this tag
+    pub tag: Option<String>,
+    /// Request body; without one, the item is sent as JSON
+    pub template: Option<Template>,
+    pub content_type: String,
+}
+
+impl Webhook {
+    /// Whether new items in this feed should be sent to the webhook
+    pub fn matches(&self, feed: &Feed) -> bool {
+        let feed_ok = self.feed.as_deref().is_none_or(|key| feed.matches_key(key));
+        let tag_ok = self
+            .tag
+            .as_deref()
+            .is_none_or(|tag| feed.tags.contains(&normalize_tag(tag)));
+        feed_ok && tag_ok
+    }
+}
+
+/// A `[feeds.<alias>]` table in config.toml
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FeedSettings {
+    /// Run for each new item in this feed, as well as the global hook
+    pub on_new_item: Option<String>,
+}
+
+/// Where feeds and items are persisted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    /// A single JSON file, rewritten on every run
+    Json,
+    /// A SQLite database, written incrementally
+    Sqlite,
 }
 
 /// Resolved config used by the app
@@ -28,6 +159,29 @@ pub struct Config {
     pub new_line_between_items: bool,
     pub max_history_per_feed: usize,
     pub state_path: PathBuf,
+    pub item_template: Template,
+    pub opener: Option<String>,
+    pub storage: StorageKind,
+    pub database_path: PathBuf,
+    /// How long to wait for another rsso process to release the state
+    pub lock_timeout_secs: u64,
+    /// Refresh on `refresh_age_mins` alone, even if a feed asks for less
+    pub ignore_feed_hints: bool,
+    /// Command run for every new item in any feed
+    pub on_new_item: Option<String>,
+    pub feed_settings: HashMap<String, FeedSettings>,
+    pub webhooks: Vec<Webhook>,
+    pub filters: Vec<Filter>,
+}
+
+impl Config {
+    /// The `[feeds.<key>]` settings for a feed, if any key matches it
+    pub fn settings_for(&self, feed: &Feed) -> Option<&FeedSettings> {
+        self.feed_settings
+            .iter()
+            .find(|(key, _)| feed.matches_key(key))
+            .map(|(_, settings)| settings)
+    }
 }
 
 /// Load config from ~/.config/rsso/config.toml if it exists,
@@ -38,6 +192,10 @@ pub struct Config {
 /// new_line_between_items = false
 /// max_history_per_feed = 200
 /// state_file = "/path/to/state.json"
+/// storage = "json"
+/// database_file = "/path/to/rsso.db"
+/// lock_timeout_secs = 30
+/// ignore_feed_hints = false
 pub fn load_config() -> Result<Config> {
     let config_path = config_dir()
         .unwrap_or_else(|| PathBuf::from("."))
@@ -76,11 +234,119 @@ pub fn load_config() -> Result<Config> {
                 .join("state.json")
         });
 
+    let item_template = match raw.as_ref().and_then(|c| c.item_template.as_deref()) {
+        Some(t) => Template::parse(t).context("Invalid item_template in config.toml")?,
+        None => Template::default(),
+    };
+
+    let opener = raw.as_ref().and_then(|c| c.opener.clone());
+
+    let storage = raw
+        .as_ref()
+        .and_then(|c| c.storage)
+        .unwrap_or(StorageKind::Json);
+
+    let database_path = raw
+        .as_ref()
+        .and_then(|c| c.database_file.clone())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            data_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("rsso")
+                .join("rsso.db")
+        });
+
+    let lock_timeout_secs = raw.as_ref().and_then(|c| c.lock_timeout_secs).unwrap_or(30);
+
+    let ignore_feed_hints = raw
+        .as_ref()
+        .and_then(|c| c.ignore_feed_hints)
+        .unwrap_or(false);
+
+    let on_new_item = raw.as_ref().and_then(|c| c.on_new_item.clone());
+
+    let feed_settings = raw
+        .as_mut()
+        .and_then(|c| c.feeds.take())
+        .unwrap_or_default();
+
+    let filters = raw
+        .as_mut()
+        .and_then(|c| c.filters.take())
+        .unwrap_or_default()
+        .into_iter()
+        .map(RawFilter::resolve)
+        .collect::<Result<Vec<Filter>>>()
+        .context("Invalid [[filters]] in config.toml")?;
+
+    let webhooks = raw
+        .and_then(|c| c.webhooks)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|w| {
+            let template = match w.template.as_deref() {
+                Some(t) => Some(Template::parse(t).with_context(|| {
+                    format!("Invalid template for webhook {} in config.toml", w.url)
+                })?),
+                None => None,
+            };
+            // Templated bodies are free-form text unless declared otherwise
+            let content_type = w.content_type.unwrap_or_else(|| {
+                if template.is_some() {
+                    "text/plain"
+                } else {
+                    "application/json"
+                }
+                .to_string()
+            });
+            Ok(Webhook {
+                url: w.url,
+                feed: w.feed,
+                tag: w.tag,
+                template,
+                content_type,
+            })
+        })
+        .collect::<Result<Vec<Webhook>>>()?;
+
     Ok(Config {
         default_limit,
         refresh_age_mins,
         new_line_between_items,
         max_history_per_feed,
         state_path,
+        item_template,
+        opener,
+        storage,
+        database_path,
+        lock_timeout_secs,
+        ignore_feed_hints,
+        on_new_item,
+        feed_settings,
+        webhooks,
+        filters,
     })
 }
+
+/// The default settings, with the state file and database kept in `dir`
+#[cfg(test)]
+pub fn test_config(dir: &std::path::Path) -> Config {
+    Config {
+        default_limit: 20,
+        refresh_age_mins: 60,
+        new_line_between_items: false,
+        max_history_per_feed: 200,
+        state_path: dir.join("state.json"),
+        item_template: Template::default(),
+        opener: None,
+        storage: StorageKind::Json,
+        database_path: dir.join("rsso.db"),
+        lock_timeout_secs: 30,
+        ignore_feed_hints: false,
+        on_new_item: None,
+        feed_settings: HashMap::new(),
+        webhooks: Vec::new(),
+        filters: Vec::new(),
+    }
+}
@@ -0,0 +1,393 @@
+use anyhow::Result;
+use feed_rs::parser;
+use reqwest::header::{CONTENT_TYPE, HeaderMap};
+use reqwest::{Client, Url};
+
+use crate::fetch::{FeedDocument, FetchError};
+
+/// Feed MIME types advertised by `<link rel="alternate">` tags
+const FEED_TYPES: [&str; 3] = [
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/feed+json",
+];
+
+/// Content types a broken feed might be served as, besides `FEED_TYPES`
+const XML_TYPES: [&str; 3] = ["application/xml", "text/xml", "application/json"];
+
+/// Paths many sites serve their feed from, tried when a page doesn't
+/// advertise one
+const COMMON_PATHS: [&str; 7] = [
+    "/feed",
+    "/rss.xml",
+    "/atom.xml",
+    "/feed.xml",
+    "/index.xml",
+    "/rss",
+    "/feed.json",
+];
+
+/// A feed found while looking at a URL
+#[derive(Debug, Clone)]
+pub struct FeedCandidate {
+    pub url: String,
+    pub title: Option<String>,
+    /// The feed itself, if it was downloaded while looking
+    pub document: Option<FeedDocument>,
+    /// Found by trying a common path, rather than linked from the page, so
+    /// it may not be the feed that was meant
+    pub guessed: bool,
+}
+
+/// Work out which feed(s) a URL refers to.
+///
+/// If the URL is already a feed, that is the only candidate. Otherwise it is
+/// treated as a web page: feeds advertised in its `<link rel="alternate">`
+/// tags are returned, or failing that (and only for an HTML page) the first
+/// common feed path that parses. An empty result means nothing was found.
+///
+/// A URL that is served as a feed (or starts like one) but doesn't parse is
+/// a `FetchError::Parse`, rather than a page to look for feeds in.
+pub async fn discover_feeds(client: &Client, url: &str) -> Result<Vec<FeedCandidate>> {
+    let resp = client.get(url).send().await.map_err(FetchError::from)?;
+    if !resp.status().is_success() {
+        return Err(FetchError::Status(resp.status()).into());
+    }
+
+    // Resolve relative links against where we ended up after redirects
+    let page_url = resp.url().clone();
+    let headers = resp.headers().clone();
+    let bytes = resp.bytes().await.map_err(FetchError::from)?;
+
+    match parser::parse(&bytes[..]) {
+        Ok(parsed) => {
+            return Ok(vec![FeedCandidate {
+                url: url.to_string(),
+                title: parsed.title.map(|t| t.content),
+                document: Some(FeedDocument {
+                    headers,
+                    body: bytes.to_vec(),
+                }),
+                guessed: false,
+            }]);
+        }
+        Err(err) if looks_like_feed(&headers, &bytes) => {
+            return Err(FetchError::Parse(err.to_string()).into());
+        }
+        Err(_) => {}
+    }
+
+    let html = String::from_utf8_lossy(&bytes);
+    let mut candidates: Vec<FeedCandidate> = Vec::new();
+
+    for attrs in link_tags(&html) {
+        let rel = attr(&attrs, "rel").unwrap_or_default().to_lowercase();
+        let kind = attr(&attrs, "type").unwrap_or_default().to_lowercase();
+        if !rel.split_whitespace().any(|r| r == "alternate") || !FEED_TYPES.contains(&kind.as_str())
+        {
+            continue;
+        }
+
+        let Some(href) = attr(&attrs, "href") else {
+            continue;
+        };
+        let Ok(resolved) = page_url.join(&href) else {
+            continue;
+        };
+
+        if !candidates.iter().any(|c| c.url == resolved.as_str()) {
+            candidates.push(FeedCandidate {
+                url: resolved.to_string(),
+                title: attr(&attrs, "title"),
+                document: None,
+                guessed: false,
+            });
+        }
+    }
+
+    if !candidates.is_empty() || !is_html(&headers, &bytes) {
+        return Ok(candidates);
+    }
+
+    for path in COMMON_PATHS {
+        let Ok(guess) = page_url.join(path) else {
+            continue;
+        };
+        if let Some(found) = probe_feed(client, &guess).await {
+            return Ok(vec![found]);
+        }
+    }
+
+    Ok(candidates)
+}
+
+/// Fetch a URL and check that it parses as a feed
+async fn probe_feed(client: &Client, url: &Url) -> Option<FeedCandidate> {
+    let resp = client.get(url.clone()).send().await.ok()?;
+    if !resp.status().is_success() {
+        return None;
+    }
+    let headers = resp.headers().clone();
+    let bytes = resp.bytes().await.ok()?;
+    let parsed = parser::parse(&bytes[..]).ok()?;
+    Some(FeedCandidate {
+        url: url.to_string(),
+        title: parsed.title.map(|t| t.content),
+        document: Some(FeedDocument {
+            headers,
+            body: bytes.to_vec(),
+        }),
+        guessed: true,
+    })
+}
+
+/// Whether a response is meant to be a feed, going by its Content-Type or,
+/// failing that, how the body starts
+fn looks_like_feed(headers: &HeaderMap, body: &[u8]) -> bool {
+    if let Some(kind) = content_type(headers)
+        && (FEED_TYPES.contains(&kind.as_str()) || XML_TYPES.contains(&kind.as_str()))
+    {
+        return true;
+    }
+
+    let start = body_start(body);
+    ["<?xml", "<rss", "<feed", "<rdf:rdf"]
+        .iter()
+        .any(|prefix| start.starts_with(prefix))
+        && !start.contains("<html")
+}
+
+/// Whether a response is a web page, going by its Content-Type or, if it
+/// has none, how the body starts
+fn is_html(headers: &HeaderMap, body: &[u8]) -> bool {
+    match content_type(headers) {
+        Some(kind) => kind == "text/html" || kind == "application/xhtml+xml",
+        None => {
+            let start = body_start(body);
+            start.starts_with("<!doctype html") || start.contains("<html")
+        }
+    }
+}
+
+/// The media type of a response, lowercased and without parameters
+fn content_type(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_ascii_lowercase())
+}
+
+/// The first few hundred bytes of a body, lowercased, without leading
+/// whitespace or byte order mark
+fn body_start(body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(&body[..body.len().min(512)]);
+    text.trim_start_matches('\u{feff}')
+        .trim_start()
+        .to_ascii_lowercase()
+}
+
+/// Attributes of every `<link ...>` tag in an HTML document, as
+/// (lowercased name, value) pairs.
+///
+/// This is a small tag scanner rather than a full HTML parser; `<link>`
+/// tags are simple enough for that.
+fn link_tags(html: &str) -> Vec<Vec<(String, String)>> {
+    let lower = html.to_ascii_lowercase();
+    let mut tags = Vec::new();
+    let mut pos = 0;
+
+    while let Some(found) = lower[pos..].find("<link") {
+        let start = pos + found + "<link".len();
+
+        // Must be the whole tag name, not e.g. <linkage>
+        if !lower[start..].starts_with(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>') {
+            pos = start;
+            continue;
+        }
+
+        let (attrs, end) = parse_attributes(html, start);
+        tags.push(attrs);
+        pos = end;
+    }
+
+    tags
+}
+
+/// Parse attributes from `start` up to the closing '>', returning them with
+/// the position just past the tag
+fn parse_attributes(html: &str, start: usize) -> (Vec<(String, String)>, usize) {
+    let bytes = html.as_bytes();
+    let mut attrs = Vec::new();
+    let mut i = start;
+
+    let skip_ws = |i: &mut usize| {
+        while *i < bytes.len() && bytes[*i].is_ascii_whitespace() {
+            *i += 1;
+        }
+    };
+
+    loop {
+        skip_ws(&mut i);
+        if i >= bytes.len() {
+            return (attrs, i);
+        }
+        if bytes[i] == b'>' {
+            return (attrs, i + 1);
+        }
+        if bytes[i] == b'/' {
+            i += 1;
+            continue;
+        }
+
+        // Attribute name
+        let name_start = i;
+        while i < bytes.len() && !bytes[i].is_ascii_whitespace() && !b"=>/".contains(&bytes[i]) {
+            i += 1;
+        }
+        let name = html[name_start..i].to_ascii_lowercase();
+
+        skip_ws(&mut i);
+        if i >= bytes.len() || bytes[i] != b'=' {
+            attrs.push((name, String::new()));
+            continue;
+        }
+        i += 1;
+        skip_ws(&mut i);
+
+        // Attribute value: quoted or bare
+        let value = if i < bytes.len() && (bytes[i] == b'"' || bytes[i] == b'\'') {
+            let quote = bytes[i];
+            let value_start = i + 1;
+            let value_end = html[value_start..]
+                .bytes()
+                .position(|b| b == quote)
+                .map(|p| value_start + p)
+                .unwrap_or(bytes.len());
+            i = (value_end + 1).min(bytes.len());
+            &html[value_start..value_end]
+        } else {
+            let value_start = i;
+            while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                i += 1;
+            }
+            &html[value_start..i]
+        };
+
+        attrs.push((name, decode_entities(value)));
+    }
+}
+
+fn attr(attrs: &[(String, String)], name: &str) -> Option<String> {
+    attrs
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Decode the few entities that show up in attribute values
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn served_as(content_type: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        headers
+    }
+
+    #[test]
+    fn feed_content_types_look_like_feeds() {
+        for kind in [
+            "application/rss+xml",
+            "application/atom+xml; charset=utf-8",
+            "text/xml",
+            "application/feed+json",
+        ] {
+            assert!(looks_like_feed(&served_as(kind), b"garbage"), "{kind}");
+        }
+        assert!(!looks_like_feed(&served_as("text/html"), b"<html>"));
+    }
+
+    #[test]
+    fn xml_bodies_look_like_feeds_whatever_they_are_served_as() {
+        let none = HeaderMap::new();
+        assert!(looks_like_feed(
+            &none,
+            b"<?xml version=\"1.0\"?><rss><channel>"
+        ));
+        assert!(looks_like_feed(
+            &served_as("text/plain"),
+            b"\n  <rss version=\"2.0\">"
+        ));
+        assert!(looks_like_feed(&none, "\u{feff}<feed>".as_bytes()));
+        assert!(!looks_like_feed(&none, b"<!DOCTYPE html><html>"));
+        assert!(!looks_like_feed(&none, b"<?xml version=\"1.0\"?><html>"));
+    }
+
+    #[test]
+    fn only_web_pages_are_searched_for_feeds() {
+        let none = HeaderMap::new();
+        assert!(is_html(&served_as("text/html; charset=utf-8"), b""));
+        assert!(is_html(&none, b"  <!DOCTYPE html><html>"));
+        assert!(!is_html(&served_as("text/plain"), b"<html>"));
+        assert!(!is_html(&served_as("application/xml"), b"<?xml?><rss>"));
+        assert!(!is_html(&none, b"hello"));
+    }
+
+    #[test]
+    fn link_tags_are_read_with_any_quoting() {
+        let html = r#"<html><head>
+<LINK REL="alternate" type='application/rss+xml' title="News &amp; Notes" href=/feed.xml>
+<linkage rel="alternate">
+<link rel=stylesheet href="/style.css"/>
+<link
+  rel="alternate"
+  type="application/atom+xml" href="https://example.com/atom">
+</head></html>"#;
+
+        let tags = link_tags(html);
+        assert_eq!(tags.len(), 3);
+        assert_eq!(attr(&tags[0], "rel").as_deref(), Some("alternate"));
+        assert_eq!(
+            attr(&tags[0], "type").as_deref(),
+            Some("application/rss+xml")
+        );
+        assert_eq!(attr(&tags[0], "title").as_deref(), Some("News & Notes"));
+        assert_eq!(attr(&tags[0], "href").as_deref(), Some("/feed.xml"));
+        assert_eq!(attr(&tags[1], "href").as_deref(), Some("/style.css"));
+        assert_eq!(
+            attr(&tags[2], "href").as_deref(),
+            Some("https://example.com/atom")
+        );
+    }
+
+    #[test]
+    fn attributes_without_values_or_closing_are_kept() {
+        let html = "<link async rel = \"alternate\" title=\"\" href=\"/feed";
+        let (attrs, end) = parse_attributes(html, "<link".len());
+        assert_eq!(end, html.len());
+        assert_eq!(
+            attrs,
+            [
+                ("async".to_string(), String::new()),
+                ("rel".to_string(), "alternate".to_string()),
+                ("title".to_string(), String::new()),
+                ("href".to_string(), "/feed".to_string()),
+            ]
+        );
+        // Empty values count as missing
+        assert_eq!(attr(&attrs, "title"), None);
+    }
+}
@@ -1,22 +1,165 @@
-use crate::state::{Feed, Item};
-use anyhow::{Result, anyhow};
-use chrono::{DateTime, Utc};
+use crate::state::{Feed, Item, MAX_HINT_MINS, RefreshHints, item_id};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc, Weekday};
 use feed_rs::parser;
-use reqwest::Client;
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use reqwest::header::{
+    AGE, CACHE_CONTROL, ETAG, EXPIRES, HeaderMap, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::{Client, StatusCode};
+use std::error::Error as StdError;
+use std::fmt;
+
+/// A freshly downloaded and parsed feed
+pub struct FetchedFeed {
+    pub title: Option<String>,
+    pub items: Vec<Item>,
+    /// Validators to send back on the next request
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// How often the feed and the server ask to be fetched
+    pub hints: RefreshHints,
+}
+
+/// A feed as downloaded, before it's parsed for a particular subscription
+#[derive(Debug, Clone)]
+pub struct FeedDocument {
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// Outcome of a successful fetch
+pub enum FetchOutcome {
+    Updated(FetchedFeed),
+    /// The server answered 304: nothing changed since the last fetch. The
+    /// response may still say how long that will stay true.
+    NotModified {
+        fresh_until: Option<DateTime<Utc>>,
+    },
+}
+
+/// Why fetching a feed failed, in terms a user can act on
+#[derive(Debug)]
+pub enum FetchError {
+    /// The host name didn't resolve (typo, or the domain is gone)
+    Dns {
+        host: String,
+    },
+    /// The host resolved but refused or dropped the connection
+    Connect {
+        host: String,
+    },
+    Timeout,
+    /// The server answered with a non-success status
+    Status(StatusCode),
+    /// We got a document, but it isn't a feed we can read
+    Parse(String),
+    /// Anything else that went wrong with the request
+    Request(String),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::Dns { host } => write!(f, "DNS lookup failed for {host}"),
+            FetchError::Connect { host } => write!(f, "Could not connect to {host}"),
+            FetchError::Timeout => write!(f, "Request timed out"),
+            FetchError::Status(status) => write!(f, "HTTP error {status}"),
+            FetchError::Parse(err) => write!(f, "Could not parse feed: {err}"),
+            FetchError::Request(err) => write!(f, "Request failed: {err}"),
+        }
+    }
+}
+
+impl StdError for FetchError {}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> Self {
+        let host = err
+            .url()
+            .and_then(|u| u.host_str())
+            .unwrap_or("host")
+            .to_string();
+
+        // reqwest doesn't expose DNS failures directly; they show up as a
+        // connect error whose source mentions the resolver
+        let mut source = err.source();
+        while let Some(e) = source {
+            let msg = e.to_string().to_lowercase();
+            if msg.contains("dns error") || msg.contains("failed to lookup address") {
+                return FetchError::Dns { host };
+            }
+            source = e.source();
+        }
+
+        if err.is_timeout() {
+            FetchError::Timeout
+        } else if err.is_connect() {
+            FetchError::Connect { host }
+        } else if let Some(status) = err.status() {
+            FetchError::Status(status)
+        } else {
+            FetchError::Request(err.to_string())
+        }
+    }
+}
 
 /// Fetch and parse a feed.
-/// Returns (title, Vec<Item>) on success.
-pub async fn fetch_feed(client: &Client, feed: &Feed) -> Result<(Option<String>, Vec<Item>)> {
-    let resp = client.get(&feed.url).send().await?;
+///
+/// Sends `If-None-Match`/`If-Modified-Since` when we have validators from a
+/// previous fetch, so unchanged feeds cost a 304 instead of a full download.
+pub async fn fetch_feed(client: &Client, feed: &Feed) -> Result<FetchOutcome> {
+    let mut req = client.get(&feed.url);
+    if let Some(etag) = &feed.etag {
+        req = req.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &feed.last_modified {
+        req = req.header(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let resp = req.send().await.map_err(FetchError::from)?;
+
+    if resp.status() == StatusCode::NOT_MODIFIED {
+        let fresh_until = fresh_until(resp.headers(), Utc::now());
+        return Ok(FetchOutcome::NotModified { fresh_until });
+    }
 
     if !resp.status().is_success() {
-        return Err(anyhow!("HTTP error {}", resp.status()));
+        return Err(FetchError::Status(resp.status()).into());
     }
 
-    let bytes = resp.bytes().await?;
-    let parsed = parser::parse(&bytes[..])?;
+    let headers = resp.headers().clone();
+    let bytes = resp.bytes().await.map_err(FetchError::from)?;
+    Ok(FetchOutcome::Updated(parse_feed(feed, &headers, &bytes)?))
+}
+
+/// Parse a downloaded feed into items for `feed`, taking its validators and
+/// refresh hints from the response headers
+pub fn parse_feed(feed: &Feed, headers: &HeaderMap, bytes: &[u8]) -> Result<FetchedFeed> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+    let fresh_until = fresh_until(headers, Utc::now());
+
+    // Leave ids empty when the feed doesn't declare them, rather than letting
+    // feed-rs derive one from the title (which would change with the title)
+    let parsed = parser::Builder::new()
+        .id_generator(|_, _, _| String::new())
+        .build()
+        .parse(bytes)
+        .map_err(|e| FetchError::Parse(e.to_string()))?;
 
     let feed_title = parsed.title.map(|t| t.content);
+    let hints = RefreshHints {
+        fresh_until,
+        ..feed_hints(bytes)
+    };
 
     let mut items = Vec::new();
 
@@ -29,28 +172,288 @@ pub async fn fetch_feed(client: &Client, feed: &Feed) -> Result<(Option<String>,
 
         let link = entry
             .links
-            .get(0)
+            .first()
             .map(|l| l.href.clone())
             .unwrap_or_else(|| "".to_string());
 
-        let published_at = entry.published.map(|d| DateTime::<Utc>::from(d));
+        let published_at = entry.published;
 
-        let updated_at = entry.updated.map(|d| DateTime::<Utc>::from(d));
+        let updated_at = entry.updated;
 
         let summary = entry.summary.as_ref().map(|s| s.content.clone());
 
-        let item = Item {
+        // Full body, kept as HTML; plain-text bodies are escaped so they can
+        // go through the same renderer
+        let content = entry.content.as_ref().and_then(|c| {
+            let body = c.body.clone()?;
+            if c.content_type.to_string().starts_with("text/plain") {
+                Some(
+                    body.replace('&', "&amp;")
+                        .replace('<', "&lt;")
+                        .replace('>', "&gt;"),
+                )
+            } else {
+                Some(body)
+            }
+        });
+
+        let author = entry.authors.first().map(person_name);
+
+        let guid = Some(entry.id.trim().to_string()).filter(|id| !id.is_empty());
+
+        let mut item = Item {
+            id: String::new(),
             feed_id: feed.id.clone(),
+            guid,
             title,
             link,
             summary,
+            content,
+            author,
             published_at,
             updated_at,
             first_seen_at: Utc::now(),
+            read: false,
         };
 
+        item.id = item_id(&feed.url, item.key());
+
         items.push(item);
     }
 
-    Ok((feed_title, items))
+    Ok(FetchedFeed {
+        title: feed_title,
+        items,
+        etag,
+        last_modified,
+        hints,
+    })
+}
+
+/// When a response stops being fresh: `Cache-Control: max-age` (less any
+/// `Age`), else `Expires`. `no-cache` and `no-store` mean it never was.
+fn fresh_until(headers: &HeaderMap, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    let latest = now + Duration::minutes(MAX_HINT_MINS as i64);
+
+    if let Some(cache_control) = header(CACHE_CONTROL) {
+        let mut max_age = None;
+        for directive in cache_control.split(',').map(|d| d.trim().to_lowercase()) {
+            if directive == "no-cache" || directive == "no-store" {
+                return None;
+            }
+            if let Some(secs) = directive.strip_prefix("max-age=") {
+                max_age = secs.trim_matches('"').parse::<i64>().ok();
+            }
+        }
+        if let Some(max_age) = max_age {
+            let age = header(AGE).and_then(|a| a.trim().parse::<i64>().ok());
+            let secs = (max_age - age.unwrap_or(0)).clamp(0, MAX_HINT_MINS as i64 * 60);
+            return Some(now + Duration::seconds(secs));
+        }
+    }
+
+    let expires = DateTime::parse_from_rfc2822(header(EXPIRES)?).ok()?;
+    Some(expires.with_timezone(&Utc).min(latest))
+}
+
+/// The refresh hints an RSS feed declares about itself: `<ttl>`,
+/// `<skipHours>`, `<skipDays>` and the syndication module's
+/// `<sy:updatePeriod>`/`<sy:updateFrequency>`. Anything malformed is
+/// ignored; Atom feeds have none of these.
+fn feed_hints(xml: &[u8]) -> RefreshHints {
+    let mut hints = RefreshHints::default();
+    let mut period_mins: Option<u64> = None;
+    let mut frequency: Option<u64> = None;
+
+    let mut reader = Reader::from_reader(xml);
+    let mut buf = Vec::new();
+    // Local names of the open elements
+    let mut path: Vec<Vec<u8>> = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) => path.push(e.local_name().as_ref().to_vec()),
+            Ok(Event::End(_)) => {
+                path.pop();
+            }
+            Ok(Event::Text(t)) => {
+                // Items may carry elements with the same names
+                if path.iter().any(|n| n == b"item" || n == b"entry") {
+                    continue;
+                }
+                let Ok(text) = t.unescape() else {
+                    continue;
+                };
+                let text = text.trim();
+                let parent = path.len().checked_sub(2).map(|i| path[i].as_slice());
+
+                match (parent, path.last().map(|n| n.as_slice())) {
+                    (_, Some(b"ttl")) => hints.ttl_mins = text.parse().ok(),
+                    (Some(b"skipHours"), Some(b"hour")) => {
+                        // Some feeds write midnight as 24
+                        if let Ok(hour) = text.parse::<u32>() {
+                            hints.skip_hours.insert(hour % 24);
+                        }
+                    }
+                    (Some(b"skipDays"), Some(b"day")) => {
+                        if let Ok(day) = text.parse::<Weekday>() {
+                            hints.skip_days.insert(day.num_days_from_monday());
+                        }
+                    }
+                    (_, Some(b"updatePeriod")) => {
+                        period_mins = match text.to_lowercase().as_str() {
+                            "hourly" => Some(60),
+                            "daily" => Some(24 * 60),
+                            "weekly" => Some(7 * 24 * 60),
+                            "monthly" => Some(30 * 24 * 60),
+                            "yearly" => Some(365 * 24 * 60),
+                            _ => None,
+                        }
+                    }
+                    (_, Some(b"updateFrequency")) => frequency = text.parse().ok(),
+                    _ => {}
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    // The module's defaults are daily and once per period
+    if period_mins.is_some() || frequency.is_some() {
+        let period = period_mins.unwrap_or(24 * 60);
+        hints.update_interval_mins = Some(period / frequency.unwrap_or(1).max(1));
+    }
+    hints
+}
+
+/// Display name for an entry author.
+///
+/// feed-rs names RSS `<author>` people literally "author" and puts the
+/// element text (conventionally "email (Name)") in `email`.
+fn person_name(p: &feed_rs::model::Person) -> String {
+    match (&p.email, p.name.as_str()) {
+        (Some(email), "author") => match email.split_once('(') {
+            Some((_, rest)) => rest.trim_end_matches(')').trim().to_string(),
+            None => email.trim().to_string(),
+        },
+        _ => p.name.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn headers(pairs: &[(reqwest::header::HeaderName, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(name, HeaderValue::from_str(value).unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn rss_hints_are_read_from_the_channel() {
+        let xml = br#"<?xml version="1.0"?>
+<rss version="2.0" xmlns:sy="http://purl.org/rss/1.0/modules/syndication/">
+  <channel>
+    <title>Hints</title>
+    <ttl> 90 </ttl>
+    <sy:updatePeriod>Hourly</sy:updatePeriod>
+    <sy:updateFrequency>4</sy:updateFrequency>
+    <skipHours><hour>24</hour><hour>3</hour><hour>nope</hour></skipHours>
+    <skipDays><day>Saturday</day><day>Sunday</day><day>Someday</day></skipDays>
+    <item><title>Post</title><ttl>5</ttl></item>
+  </channel>
+</rss>"#;
+        let hints = feed_hints(xml);
+        assert_eq!(hints.ttl_mins, Some(90));
+        assert_eq!(hints.update_interval_mins, Some(15));
+        assert_eq!(hints.skip_hours, [0, 3].into());
+        assert_eq!(hints.skip_days, [5, 6].into());
+        assert_eq!(hints.fresh_until, None);
+    }
+
+    #[test]
+    fn update_period_defaults_follow_the_syndication_module() {
+        let hints =
+            feed_hints(b"<rss><channel><sy:updateFrequency>2</sy:updateFrequency></channel></rss>");
+        assert_eq!(hints.update_interval_mins, Some(12 * 60));
+        let hints =
+            feed_hints(b"<rss><channel><sy:updatePeriod>weekly</sy:updatePeriod></channel></rss>");
+        assert_eq!(hints.update_interval_mins, Some(7 * 24 * 60));
+        let hints =
+            feed_hints(b"<rss><channel><sy:updateFrequency>0</sy:updateFrequency></channel></rss>");
+        assert_eq!(hints.update_interval_mins, Some(24 * 60));
+    }
+
+    #[test]
+    fn feeds_without_hints_have_none() {
+        let atom = br#"<feed xmlns="http://www.w3.org/2005/Atom"><title>A</title>
+<entry><title>Post</title></entry></feed>"#;
+        assert_eq!(feed_hints(atom), RefreshHints::default());
+        assert_eq!(
+            feed_hints(b"<rss><channel><ttl>soon</ttl>"),
+            RefreshHints::default()
+        );
+    }
+
+    #[test]
+    fn max_age_less_age_beats_expires() {
+        let now = at("2024-05-10T12:00:00Z");
+        let h = headers(&[
+            (CACHE_CONTROL, "public, max-age=600"),
+            (AGE, "120"),
+            (EXPIRES, "Fri, 10 May 2024 18:00:00 GMT"),
+        ]);
+        assert_eq!(fresh_until(&h, now), Some(at("2024-05-10T12:08:00Z")));
+
+        // Already older than max-age
+        let h = headers(&[(CACHE_CONTROL, "max-age=60"), (AGE, "300")]);
+        assert_eq!(fresh_until(&h, now), Some(now));
+    }
+
+    #[test]
+    fn expires_is_used_without_max_age() {
+        let now = at("2024-05-10T12:00:00Z");
+        let h = headers(&[
+            (CACHE_CONTROL, "public"),
+            (EXPIRES, "Fri, 10 May 2024 13:30:00 GMT"),
+        ]);
+        assert_eq!(fresh_until(&h, now), Some(at("2024-05-10T13:30:00Z")));
+
+        let h = headers(&[(EXPIRES, "0")]);
+        assert_eq!(fresh_until(&h, now), None);
+        assert_eq!(fresh_until(&HeaderMap::new(), now), None);
+    }
+
+    #[test]
+    fn no_cache_means_never_fresh() {
+        let now = at("2024-05-10T12:00:00Z");
+        for cache_control in ["no-cache", "max-age=600, No-Store"] {
+            let h = headers(&[
+                (CACHE_CONTROL, cache_control),
+                (EXPIRES, "Fri, 10 May 2024 13:30:00 GMT"),
+            ]);
+            assert_eq!(fresh_until(&h, now), None, "{cache_control}");
+        }
+    }
+
+    #[test]
+    fn freshness_is_capped() {
+        let now = at("2024-05-10T12:00:00Z");
+        let cap = now + Duration::minutes(MAX_HINT_MINS as i64);
+        let h = headers(&[(CACHE_CONTROL, "max-age=31536000")]);
+        assert_eq!(fresh_until(&h, now), Some(cap));
+        let h = headers(&[(EXPIRES, "Thu, 01 Jan 2099 00:00:00 GMT")]);
+        assert_eq!(fresh_until(&h, now), Some(cap));
+    }
 }
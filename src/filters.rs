@@ -0,0 +1,262 @@
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::search::words;
+use crate::state::{Feed, Item, normalize_tag};
+use crate::template::html_to_line;
+
+/// What a `[[filters]]` rule does to the items it matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterAction {
+    /// Leave the item out of listings and new-item notifications
+    Hide,
+    /// Make the item stand out in listings
+    Highlight,
+    /// Mark the item read as soon as it is fetched
+    MarkRead,
+}
+
+/// A part of an item a rule can look at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterField {
+    Title,
+    Summary,
+    Author,
+    Link,
+}
+
+/// What a rule looks for
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Whole words, in order, ignoring case and punctuation
+    Keyword(Vec<String>),
+    Regex(Regex),
+}
+
+/// A `[[filters]]` rule
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub pattern: Pattern,
+    /// Only items from this feed (alias/title/id/url)
+    pub feed: Option<String>,
+    /// Only items from feeds with this tag
+    pub tag: Option<String>,
+    pub fields: Vec<FilterField>,
+    pub action: FilterAction,
+}
+
+impl Filter {
+    /// Whether the rule applies to this item of `feed`
+    pub fn matches(&self, feed: &Feed, item: &Item) -> bool {
+        let feed_ok = self.feed.as_deref().is_none_or(|key| feed.matches_key(key));
+        let tag_ok = self
+            .tag
+            .as_deref()
+            .is_none_or(|tag| feed.tags.contains(&normalize_tag(tag)));
+        if !feed_ok || !tag_ok {
+            return false;
+        }
+
+        self.fields.iter().any(|field| {
+            let text = match field {
+                FilterField::Title => item.title.clone(),
+                FilterField::Summary => item
+                    .summary
+                    .as_deref()
+                    .map(html_to_line)
+                    .unwrap_or_default(),
+                FilterField::Author => item.author.clone().unwrap_or_default(),
+                FilterField::Link => item.link.clone(),
+            };
+            match &self.pattern {
+                Pattern::Keyword(keyword) => words(&text)
+                    .windows(keyword.len())
+                    .any(|w| w == keyword.as_slice()),
+                Pattern::Regex(re) => re.is_match(&text),
+            }
+        })
+    }
+}
+
+/// What the rules together do to one item
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Verdict {
+    pub hide: bool,
+    pub highlight: bool,
+    pub mark_read: bool,
+}
+
+/// Apply every rule to an item of `feed`
+pub fn verdict(filters: &[Filter], feed: &Feed, item: &Item) -> Verdict {
+    let mut verdict = Verdict::default();
+    for filter in filters.iter().filter(|f| f.matches(feed, item)) {
+        match filter.action {
+            FilterAction::Hide => verdict.hide = true,
+            FilterAction::Highlight => verdict.highlight = true,
+            FilterAction::MarkRead => verdict.mark_read = true,
+        }
+    }
+    verdict
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RawFilter;
+    use chrono::{DateTime, Utc};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn feed() -> Feed {
+        Feed {
+            id: "f1".to_string(),
+            url: "https://example.com/feed.xml".to_string(),
+            alias: Some("news".to_string()),
+            title: Some("Example News".to_string()),
+            added_at: at("2024-05-01T00:00:00Z"),
+            last_fetched_at: None,
+            last_error: None,
+            group: None,
+            tags: ["tech".to_string()].into(),
+            etag: None,
+            last_modified: None,
+            overrides: Default::default(),
+            hints: Default::default(),
+        }
+    }
+
+    fn item(title: &str) -> Item {
+        Item {
+            id: "i1".to_string(),
+            feed_id: "f1".to_string(),
+            guid: None,
+            title: title.to_string(),
+            link: "https://example.com/posts/sponsored-deal".to_string(),
+            published_at: None,
+            updated_at: None,
+            summary: Some("<p>A <b>weekly</b> roundup</p>".to_string()),
+            content: None,
+            author: Some("Jane Doe".to_string()),
+            first_seen_at: at("2024-05-01T00:00:00Z"),
+            read: false,
+        }
+    }
+
+    /// Parse and check a `[[filters]]` entry as config.toml would
+    fn rule(toml_text: &str) -> anyhow::Result<Filter> {
+        toml::from_str::<RawFilter>(toml_text)?.resolve()
+    }
+
+    #[test]
+    fn keywords_match_whole_words_in_order() {
+        let filter = rule(r#"keyword = "Rust, release""#).unwrap();
+        assert!(filter.matches(&feed(), &item("The rust release notes")));
+        assert!(filter.matches(&feed(), &item("RUST: Release 1.80!")));
+        assert!(!filter.matches(&feed(), &item("Release of Rust")));
+        assert!(!filter.matches(&feed(), &item("Rusty releases")));
+    }
+
+    #[test]
+    fn regexes_match_anywhere() {
+        let filter = rule(r#"regex = "(?i)^\\[ad\\]""#).unwrap();
+        assert!(filter.matches(&feed(), &item("[AD] Buy now")));
+        assert!(!filter.matches(&feed(), &item("Read this [ad]")));
+    }
+
+    #[test]
+    fn only_the_chosen_fields_are_checked() {
+        // Title and summary by default; the summary is matched as text
+        let filter = rule(r#"keyword = "weekly roundup""#).unwrap();
+        assert!(filter.matches(&feed(), &item("Untitled")));
+        let filter = rule(r#"keyword = "jane""#).unwrap();
+        assert!(!filter.matches(&feed(), &item("Untitled")));
+
+        let filter = rule(
+            r#"keyword = "jane"
+fields = ["author"]"#,
+        )
+        .unwrap();
+        assert!(filter.matches(&feed(), &item("Untitled")));
+        let filter = rule(
+            r#"regex = "/sponsored-"
+fields = ["link"]"#,
+        )
+        .unwrap();
+        assert!(filter.matches(&feed(), &item("Untitled")));
+        assert!(
+            !rule(r#"regex = "/sponsored-""#)
+                .unwrap()
+                .matches(&feed(), &item("Untitled"))
+        );
+    }
+
+    #[test]
+    fn feed_and_tag_scopes_limit_rules() {
+        let title = "Sponsored post";
+        for (scope, applies) in [
+            (r#"feed = "NEWS""#, true),
+            (r#"feed = "example news""#, true),
+            (r#"feed = "other""#, false),
+            (r#"tag = "Tech""#, true),
+            (r#"tag = "sports""#, false),
+        ] {
+            let filter = rule(&format!("keyword = \"sponsored\"\n{scope}")).unwrap();
+            assert_eq!(filter.matches(&feed(), &item(title)), applies, "{scope}");
+        }
+    }
+
+    #[test]
+    fn verdict_combines_every_matching_rule() {
+        let filters = vec![
+            rule(r#"keyword = "sponsored""#).unwrap(),
+            rule(
+                r#"keyword = "rust"
+action = "highlight""#,
+            )
+            .unwrap(),
+            rule(
+                r#"keyword = "weekly"
+action = "mark-read""#,
+            )
+            .unwrap(),
+        ];
+
+        let v = verdict(&filters, &feed(), &item("Rust news"));
+        assert!(!v.hide && v.highlight && v.mark_read);
+        let v = verdict(&filters, &feed(), &item("Sponsored Rust post"));
+        assert!(v.hide && v.highlight);
+
+        let v = verdict(&[], &feed(), &item("Sponsored Rust post"));
+        assert!(!v.hide && !v.highlight && !v.mark_read);
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let err = rule(r#"regex = "(unclosed""#).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Invalid filter regex '(unclosed'"),
+            "{err:#}"
+        );
+        let err = rule(r#"keyword = " -- ""#).unwrap_err();
+        assert!(err.to_string().contains("has no words"), "{err}");
+        assert!(rule(r#"action = "hide""#).is_err());
+        assert!(
+            rule(
+                r#"keyword = "a"
+regex = "b""#
+            )
+            .is_err()
+        );
+        assert!(
+            rule(
+                r#"keyword = "a"
+action = "delete""#
+            )
+            .is_err()
+        );
+    }
+}
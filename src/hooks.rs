@@ -0,0 +1,53 @@
+use anyhow::{Context, Result, bail};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::state::Item;
+
+/// Run an `on_new_item` hook for one item.
+///
+/// The command line is run by the shell, with the item as a JSON object on
+/// stdin (the same record `--format json` prints) and its main fields in
+/// `RSSO_*` environment variables.
+pub fn run_hook(command: &str, item: &Item, feed_label: &str, json: &str) -> Result<()> {
+    let mut child = shell(command)
+        .env("RSSO_ITEM_ID", &item.id)
+        .env("RSSO_ITEM_TITLE", &item.title)
+        .env("RSSO_ITEM_LINK", &item.link)
+        .env("RSSO_ITEM_AUTHOR", item.author.as_deref().unwrap_or(""))
+        .env("RSSO_ITEM_DATE", item.date().to_rfc3339())
+        .env("RSSO_FEED", feed_label)
+        .env("RSSO_FEED_ID", &item.feed_id)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run hook `{}`", command))?;
+
+    // A hook that only reads the environment may exit without reading
+    // stdin; that's fine, so a broken pipe isn't an error
+    if let Some(mut stdin) = child.stdin.take()
+        && let Err(err) = stdin.write_all(json.as_bytes())
+        && err.kind() != std::io::ErrorKind::BrokenPipe
+    {
+        return Err(err).with_context(|| format!("Failed to write to hook `{}`", command));
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("Hook `{}` exited with {}", command, status);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.args(["/C", command]);
+    cmd
+}
+
+#[cfg(not(target_os = "windows"))]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command]);
+    cmd
+}
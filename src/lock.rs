@@ -0,0 +1,124 @@
+use anyhow::{Context, Result, bail};
+use std::fs::{File, OpenOptions, TryLockError, create_dir_all};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, StorageKind};
+
+/// How a command needs to access the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Only reads: any number of these can run at once
+    Shared,
+    /// Loads, changes and saves: nothing else may run alongside it
+    Exclusive,
+}
+
+/// An advisory lock on the store, released when dropped.
+///
+/// The lock is taken on a separate `.lock` file next to the state file or
+/// database, since saving replaces state.json with a new file.
+#[derive(Debug)]
+pub struct StoreLock {
+    _file: File,
+}
+
+/// How often to retry while another process holds the lock
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Lock the configured store, waiting up to `lock_timeout_secs` for other
+/// rsso processes to finish with it
+pub fn lock_store(cfg: &Config, mode: LockMode) -> Result<StoreLock> {
+    let path = lock_path(cfg);
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Could not open lock file {}", path.display()))?;
+
+    let timeout = Duration::from_secs(cfg.lock_timeout_secs);
+    let started = Instant::now();
+    let mut waiting = false;
+
+    loop {
+        let attempt = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+        };
+
+        match attempt {
+            Ok(()) => return Ok(StoreLock { _file: file }),
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(err)) => {
+                return Err(err).with_context(|| format!("Could not lock {}", path.display()));
+            }
+        }
+
+        if started.elapsed() >= timeout {
+            bail!(
+                "Another rsso process is still using the state (gave up after {}s; \
+                 lock file: {})",
+                cfg.lock_timeout_secs,
+                path.display()
+            );
+        }
+        if !waiting {
+            eprintln!("Waiting for another rsso process to finish...");
+            waiting = true;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// `state.json.lock` or `rsso.db.lock`, depending on the storage backend
+fn lock_path(cfg: &Config) -> PathBuf {
+    let store: &Path = match cfg.storage {
+        StorageKind::Json => &cfg.state_path,
+        StorageKind::Sqlite => &cfg.database_path,
+    };
+    let mut name = store.as_os_str().to_owned();
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::state::TestDir;
+
+    #[test]
+    fn exclusive_locks_shut_out_other_runs() {
+        let dir = TestDir::new("lock");
+        let mut cfg = test_config(&dir);
+        cfg.lock_timeout_secs = 1;
+
+        let held = lock_store(&cfg, LockMode::Exclusive).unwrap();
+
+        // Both kinds of lock wait out the timeout, then give up
+        let started = Instant::now();
+        let err = lock_store(&cfg, LockMode::Exclusive).unwrap_err();
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert!(err.to_string().contains("gave up after 1s"));
+        assert!(lock_store(&cfg, LockMode::Shared).is_err());
+
+        drop(held);
+        let _exclusive = lock_store(&cfg, LockMode::Exclusive).unwrap();
+    }
+
+    #[test]
+    fn shared_locks_run_side_by_side() {
+        let dir = TestDir::new("shared");
+        let mut cfg = test_config(&dir);
+        cfg.lock_timeout_secs = 0;
+
+        let _first = lock_store(&cfg, LockMode::Shared).unwrap();
+        let _second = lock_store(&cfg, LockMode::Shared).unwrap();
+        assert!(lock_store(&cfg, LockMode::Exclusive).is_err());
+    }
+}
@@ -1,10 +1,24 @@
 mod commands;
 mod config;
+mod discover;
 mod fetch;
+mod filters;
+mod hooks;
+mod lock;
+mod opener;
+mod opml;
+mod render;
+mod search;
+mod sqlite;
 mod state;
+mod storage;
+mod template;
+mod webhooks;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use lock::LockMode;
+use std::path::PathBuf;
 
 /// Command-line arguments for rsso
 #[derive(Parser, Debug)]
@@ -17,21 +31,67 @@ pub struct Cli {
     // Fix in config file too if changed
     pub limit: Option<usize>,
 
+    /// Only include feeds carrying this tag (repeat for any of several)
+    #[arg(long = "tag", global = true)]
+    pub tags: Vec<String>,
+
+    /// Only include feeds in this group or its subgroups
+    #[arg(long, global = true)]
+    pub group: Option<String>,
+
+    /// Output format for item and feed listings
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Item line template, e.g. "{date:%Y-%m-%d} {feed}: {title}" (overrides config)
+    #[arg(long, global = true)]
+    pub template: Option<String>,
+
+    /// Only show items that haven't been marked read
+    #[arg(long, global = true)]
+    pub unread: bool,
+
+    /// Only show items dated from this date (2024-05-01) or span ago (30m,
+    /// 12h, 2d, 1w), or with "last-run", that arrived since all feeds were
+    /// last listed
+    #[arg(long, global = true)]
+    pub since: Option<String>,
+
+    /// Only show items dated before this span ago, or up to and including
+    /// this date
+    #[arg(long, global = true)]
+    pub until: Option<String>,
+
     #[command(subcommand)]
     pub command: Option<Cmd>,
 }
 
+/// How listings are written to stdout
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Coloured, pipe-separated lines
+    Text,
+    /// A single JSON array
+    Json,
+    /// One JSON object per line
+    Ndjson,
+}
+
 /// Subcommands for rsso
 #[derive(Subcommand, Debug)]
 pub enum Cmd {
     /// Subscribe to a new feed
     Sub {
-        /// Feed URL (RSS or Atom)
+        /// Feed URL (RSS, Atom or JSON Feed), or a web page that links to one
         url: String,
 
         /// Optional alias for this feed
         #[arg(long)]
         alias: Option<String>,
+
+        /// Subscribe even if the feed can't be fetched or parsed right now
+        #[arg(long)]
+        force: bool,
     },
 
     /// Unsubscribe from a feed by alias or URL
@@ -43,10 +103,10 @@ pub enum Cmd {
     /// List subscribed feeds
     List,
 
-    /// Show items from a specific feed
+    /// Show items from a specific feed, or from all feeds matching --tag/--group
     Feed {
         /// Feed alias or URL
-        id_or_url: String,
+        id_or_url: Option<String>,
     },
 
     /// Force refresh feeds (all or selected)
@@ -63,18 +123,197 @@ pub enum Cmd {
         /// New alias to assign
         #[arg(long)]
         alias: String,
+    },
+
+    /// Add tags to a feed
+    Tag {
+        /// Feed alias/title/id/url
+        key: String,
+
+        /// One or more tags to add
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+
+    /// Remove tags from a feed
+    Untag {
+        /// Feed alias/title/id/url
+        key: String,
+
+        /// One or more tags to remove
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+
+    /// Move a feed into a group (e.g. "work/rust"), or out of any group
+    Group {
+        /// Feed alias/title/id/url
+        key: String,
+
+        /// Group path; omit to clear the feed's group
+        group: Option<String>,
+    },
+
+    /// Override settings for one feed, e.g. `rsso set news refresh=10m history=1000`
+    Set {
+        /// Feed alias/title/id/url
+        key: String,
+
+        /// refresh=<span>, history=<n>, limit=<n>, label=<name> or
+        /// disabled=<true|false>; leave the value empty to use the config
+        /// again. With none, shows the feed's settings.
+        settings: Vec<String>,
+    },
+
+    /// Mark one or more items as read
+    Read {
+        /// Item id, guid or link
+        #[arg(required = true)]
+        items: Vec<String>,
+    },
+
+    /// Open items in the browser (or the configured opener) and mark them read
+    Open {
+        /// Item ids, or positions in the listing as @N (@1 = newest). If
+        /// none are given, opens up to -n items from the selected feeds.
+        items: Vec<String>,
+
+        /// Only consider items from this feed
+        #[arg(long)]
+        feed: Option<String>,
+    },
+
+    /// Search item titles, summaries and content across all feeds
+    Search {
+        /// Words to find; "quote" phrases. Narrow down with feed:<feed>,
+        /// tag:<tag>, before:YYYY-MM-DD and after:YYYY-MM-DD
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+
+    /// Read an item's full content in the terminal (and mark it read)
+    Show {
+        /// Item id, guid or link
+        item: String,
+
+        /// Wrap text at this many columns (defaults to $COLUMNS, up to 100)
+        #[arg(long)]
+        width: Option<usize>,
+    },
+
+    /// Mark every item in a feed, or in all (selected) feeds, as read
+    #[command(group(ArgGroup::new("target").required(true).args(["feed", "all"])))]
+    MarkRead {
+        /// Feed alias/title/id/url
+        #[arg(long)]
+        feed: Option<String>,
+
+        /// Mark items in all feeds (respects --tag/--group)
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Import subscriptions from an OPML file
+    Import {
+        /// Path to the OPML file
+        file: PathBuf,
+    },
+
+    /// Export subscriptions as OPML
+    Export {
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Show the webhook delivery log, newest first (up to -n entries)
+    Deliveries,
+
+    /// Keep running, refreshing feeds as they go stale and printing new items
+    Watch,
+
+    /// Inspect or upgrade the state file
+    State {
+        #[command(subcommand)]
+        action: StateCmd,
     }, // No subcommand -> default: show recent items from all feeds
 }
 
+/// Subcommands for `rsso state`
+#[derive(Subcommand, Debug)]
+pub enum StateCmd {
+    /// Upgrade state.json to the current schema version
+    Migrate {
+        /// Show what would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+impl Cmd {
+    /// Commands that don't change feeds or items, and so can run alongside
+    /// each other under a shared lock (`open` marks what it opened read
+    /// afterwards, under a lock of its own)
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Cmd::List
+                | Cmd::Open { .. }
+                | Cmd::Search { .. }
+                | Cmd::Export { .. }
+                | Cmd::Deliveries
+                | Cmd::State {
+                    action: StateCmd::Migrate { dry_run: true }
+                }
+        )
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
     let cfg = config::load_config()?;
-    let mut state = state::load_state(&cfg)?;
 
-    commands::run_command(cli, &cfg, &mut state).await?;
+    // `watch` runs until interrupted, and only locks the state while it
+    // refreshes
+    if let Some(Cmd::Watch) = &cli.command {
+        return commands::run_watch(cli, &cfg).await;
+    }
+
+    // `sub` may ask which of a site's feeds to subscribe to, so it only
+    // locks the state once that's settled
+    if let Some(Cmd::Sub { .. }) = &cli.command {
+        return commands::run_sub(cli, &cfg).await;
+    }
+
+    // Hold the lock for the whole load-change-save cycle, so concurrent runs
+    // (say, a cron refresh and an interactive session) can't drop each
+    // other's changes
+    let read_only = cli.command.as_ref().is_some_and(Cmd::is_read_only);
+    let mode = if read_only {
+        LockMode::Shared
+    } else {
+        LockMode::Exclusive
+    };
+
+    // `state` commands work on the raw file, before it is loaded
+    if let Some(Cmd::State { action }) = &cli.command {
+        let _lock = lock::lock_store(&cfg, mode)?;
+        return commands::run_state_command(action, &cfg);
+    }
+
+    let (lock, mut store) = storage::lock_and_open(&cfg, mode)?;
+
+    let follow_up = commands::run_command(cli, &cfg, store.as_mut()).await?;
+
+    if !read_only {
+        store.save()?;
+    }
 
-    state::save_state(&cfg, &state)?;
-    Ok(())
+    // Browsers, hooks and webhooks can be slow, or run rsso themselves, so
+    // they wait until the changes are saved and other runs can get at the
+    // state
+    drop(lock);
+    commands::run_follow_up(&cfg, store.feeds(), follow_up, webhooks::INLINE_TRIES).await
 }
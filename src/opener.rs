@@ -0,0 +1,62 @@
+use anyhow::{Context, Result, bail};
+use std::env;
+use std::process::Command;
+
+use crate::config::Config;
+
+/// Open a link with the configured `opener`, else `$BROWSER`, else the
+/// platform's default handler (`xdg-open`, `open` or `start`).
+///
+/// `opener` and `$BROWSER` are split on whitespace into a program and its
+/// arguments, and the link is passed as the final argument.
+pub fn open_link(cfg: &Config, link: &str) -> Result<()> {
+    if link.is_empty() {
+        bail!("Item has no link to open");
+    }
+
+    // $BROWSER may be a ':'-separated list of candidates; use the first
+    let browser = env::var("BROWSER")
+        .ok()
+        .and_then(|b| b.split(':').next().map(|s| s.trim().to_string()))
+        .filter(|b| !b.is_empty());
+
+    let command_line = cfg.opener.clone().or(browser);
+
+    let mut cmd = match command_line {
+        Some(line) => {
+            let mut parts = line.split_whitespace();
+            let program = parts.next().context("Empty opener command")?;
+            let mut cmd = Command::new(program);
+            cmd.args(parts);
+            cmd
+        }
+        None => default_opener(),
+    };
+
+    let status = cmd
+        .arg(link)
+        .status()
+        .with_context(|| format!("Failed to run {:?}", cmd.get_program()))?;
+
+    if !status.success() {
+        bail!("{:?} exited with {}", cmd.get_program(), status);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn default_opener() -> Command {
+    Command::new("open")
+}
+
+#[cfg(target_os = "windows")]
+fn default_opener() -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.args(["/C", "start", ""]);
+    cmd
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn default_opener() -> Command {
+    Command::new("xdg-open")
+}
@@ -0,0 +1,353 @@
+use anyhow::{Result, bail};
+use chrono::Utc;
+use quick_xml::encoding::Decoder;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+use crate::state::Feed;
+
+/// A feed outline read from an OPML document
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpmlFeed {
+    pub url: String,
+    /// The outline's `text` attribute (what most readers show as the name)
+    pub text: Option<String>,
+    /// The outline's `title` attribute
+    pub title: Option<String>,
+    /// Titles of the enclosing category outlines, joined with '/'
+    pub group: Option<String>,
+}
+
+/// Read all feed outlines (those with an `xmlUrl`) from an OPML document.
+///
+/// Some readers write a feed's address as `url` instead, which is taken too
+/// on outlines of type "rss" or "atom" (on others, such as "link", `url` is
+/// a web page). Other outlines are treated as categories, and any feeds
+/// nested inside them get a group path such as "work/rust".
+pub fn parse_opml(xml: &str) -> Result<Vec<OpmlFeed>> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    // One entry per open <outline>; Some(title) for categories, None for
+    // feed outlines that happen to have children.
+    let mut stack: Vec<Option<String>> = Vec::new();
+    let mut feeds = Vec::new();
+    let mut saw_opml = false;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == b"opml" => saw_opml = true,
+            Event::Start(e) if e.name().as_ref() == b"outline" => {
+                let outline = read_outline(&e, reader.decoder())?;
+                match outline.url {
+                    Some(_) => {
+                        feeds.push(to_opml_feed(outline, &stack));
+                        stack.push(None);
+                    }
+                    None => stack.push(outline.text.or(outline.title)),
+                }
+            }
+            Event::Empty(e) if e.name().as_ref() == b"outline" => {
+                let outline = read_outline(&e, reader.decoder())?;
+                if outline.url.is_some() {
+                    feeds.push(to_opml_feed(outline, &stack));
+                }
+            }
+            Event::End(e) if e.name().as_ref() == b"outline" => {
+                stack.pop();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if !saw_opml {
+        bail!("Not an OPML document (missing <opml> element)");
+    }
+
+    Ok(feeds)
+}
+
+/// Raw attributes of a single <outline>
+struct Outline {
+    url: Option<String>,
+    text: Option<String>,
+    title: Option<String>,
+}
+
+fn read_outline(e: &BytesStart, decoder: Decoder) -> Result<Outline> {
+    let mut outline = Outline {
+        url: None,
+        text: None,
+        title: None,
+    };
+    let mut kind = None;
+    let mut plain_url = None;
+
+    for attr in e.attributes() {
+        let attr = attr?;
+        let value = attr.decode_and_unescape_value(decoder)?.trim().to_string();
+        if value.is_empty() {
+            continue;
+        }
+        match attr.key.as_ref() {
+            b"xmlUrl" => outline.url = Some(value),
+            b"url" => plain_url = Some(value),
+            b"type" => kind = Some(value.to_lowercase()),
+            b"text" => outline.text = Some(value),
+            b"title" => outline.title = Some(value),
+            _ => {}
+        }
+    }
+
+    if outline.url.is_none() && matches!(kind.as_deref(), Some("rss" | "atom")) {
+        outline.url = plain_url;
+    }
+
+    Ok(outline)
+}
+
+fn to_opml_feed(outline: Outline, stack: &[Option<String>]) -> OpmlFeed {
+    let path: Vec<&str> = stack.iter().flatten().map(|s| s.as_str()).collect();
+    let group = if path.is_empty() {
+        None
+    } else {
+        Some(path.join("/"))
+    };
+
+    OpmlFeed {
+        url: outline.url.unwrap_or_default(),
+        text: outline.text,
+        title: outline.title,
+        group,
+    }
+}
+
+/// Nested category outlines built from feed group paths, in first-seen order
+#[derive(Default)]
+struct GroupNode<'a> {
+    feeds: Vec<&'a Feed>,
+    children: Vec<(String, GroupNode<'a>)>,
+}
+
+impl<'a> GroupNode<'a> {
+    fn insert(&mut self, path: &[&str], feed: &'a Feed) {
+        let Some((first, rest)) = path.split_first() else {
+            self.feeds.push(feed);
+            return;
+        };
+
+        let pos = match self.children.iter().position(|(name, _)| name == first) {
+            Some(pos) => pos,
+            None => {
+                self.children
+                    .push((first.to_string(), GroupNode::default()));
+                self.children.len() - 1
+            }
+        };
+        self.children[pos].1.insert(rest, feed);
+    }
+}
+
+/// Write feeds out as an OPML 2.0 document.
+///
+/// Feeds with a group are nested inside category outlines named after it.
+pub fn write_opml(feeds: &[Feed]) -> Result<String> {
+    let mut root = GroupNode::default();
+    for f in feeds {
+        let path: Vec<&str> = f
+            .group
+            .as_deref()
+            .map(|g| g.split('/').filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default();
+        root.insert(&path, f);
+    }
+
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+
+    let mut opml = BytesStart::new("opml");
+    opml.push_attribute(("version", "2.0"));
+    writer.write_event(Event::Start(opml))?;
+
+    writer.write_event(Event::Start(BytesStart::new("head")))?;
+    write_text_element(&mut writer, "title", "rsso subscriptions")?;
+    write_text_element(&mut writer, "dateCreated", &Utc::now().to_rfc2822())?;
+    writer.write_event(Event::End(BytesEnd::new("head")))?;
+
+    writer.write_event(Event::Start(BytesStart::new("body")))?;
+    write_group(&mut writer, &root)?;
+    writer.write_event(Event::End(BytesEnd::new("body")))?;
+
+    writer.write_event(Event::End(BytesEnd::new("opml")))?;
+
+    let mut out = String::from_utf8(writer.into_inner())?;
+    out.push('\n');
+    Ok(out)
+}
+
+fn write_group(writer: &mut Writer<Vec<u8>>, node: &GroupNode) -> Result<()> {
+    for f in &node.feeds {
+        let text = f.alias.as_deref().or(f.title.as_deref()).unwrap_or(&f.id);
+
+        let mut outline = BytesStart::new("outline");
+        outline.push_attribute(("type", "rss"));
+        outline.push_attribute(("text", text));
+        if let Some(title) = &f.title {
+            outline.push_attribute(("title", title.as_str()));
+        }
+        outline.push_attribute(("xmlUrl", f.url.as_str()));
+        writer.write_event(Event::Empty(outline))?;
+    }
+
+    for (name, child) in &node.children {
+        let mut outline = BytesStart::new("outline");
+        outline.push_attribute(("text", name.as_str()));
+        outline.push_attribute(("title", name.as_str()));
+        writer.write_event(Event::Start(outline))?;
+        write_group(writer, child)?;
+        writer.write_event(Event::End(BytesEnd::new("outline")))?;
+    }
+
+    Ok(())
+}
+
+fn write_text_element(writer: &mut Writer<Vec<u8>>, name: &str, text: &str) -> Result<()> {
+    writer.write_event(Event::Start(BytesStart::new(name)))?;
+    writer.write_event(Event::Text(BytesText::new(text)))?;
+    writer.write_event(Event::End(BytesEnd::new(name)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn feed(id: &str, url: &str, title: &str, group: Option<&str>) -> Feed {
+        Feed {
+            id: id.to_string(),
+            url: url.to_string(),
+            alias: Some(id.to_string()),
+            title: Some(title.to_string()),
+            added_at: DateTime::UNIX_EPOCH,
+            last_fetched_at: None,
+            last_error: None,
+            group: group.map(str::to_string),
+            tags: Default::default(),
+            etag: None,
+            last_modified: None,
+            overrides: Default::default(),
+            hints: Default::default(),
+        }
+    }
+
+    fn parsed(url: &str, text: &str, title: Option<&str>, group: Option<&str>) -> OpmlFeed {
+        OpmlFeed {
+            url: url.to_string(),
+            text: Some(text.to_string()),
+            title: title.map(str::to_string),
+            group: group.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn groups_round_trip_as_nested_outlines() {
+        let feeds = [
+            feed(
+                "rust",
+                "https://blog.rust-lang.org/feed.xml",
+                "Rust Blog",
+                Some("work/rust"),
+            ),
+            feed("news", "https://news.example.com/rss", "News", None),
+            feed(
+                "go",
+                "https://go.dev/blog/feed.atom",
+                "Go Blog",
+                Some("work"),
+            ),
+        ];
+        let xml = write_opml(&feeds).unwrap();
+
+        assert_eq!(
+            parse_opml(&xml).unwrap(),
+            [
+                parsed("https://news.example.com/rss", "news", Some("News"), None),
+                parsed(
+                    "https://go.dev/blog/feed.atom",
+                    "go",
+                    Some("Go Blog"),
+                    Some("work")
+                ),
+                parsed(
+                    "https://blog.rust-lang.org/feed.xml",
+                    "rust",
+                    Some("Rust Blog"),
+                    Some("work/rust")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn special_characters_round_trip() {
+        let feeds = [feed(
+            "qa",
+            "https://example.com/feed?a=1&b=2",
+            "Q&A <weekly> \"digest\"",
+            Some("R&D"),
+        )];
+        let xml = write_opml(&feeds).unwrap();
+        assert!(xml.contains("Q&amp;A &lt;weekly&gt;"));
+        assert!(xml.contains("feed?a=1&amp;b=2"));
+
+        assert_eq!(
+            parse_opml(&xml).unwrap(),
+            [parsed(
+                "https://example.com/feed?a=1&b=2",
+                "qa",
+                Some("Q&A <weekly> \"digest\""),
+                Some("R&D")
+            )]
+        );
+    }
+
+    #[test]
+    fn feed_urls_come_from_xml_url_or_url_on_feed_outlines() {
+        let xml = r#"<?xml version="1.0"?>
+<opml version="1.0"><body>
+  <outline text="A" type="rss" xmlUrl="https://a.example.com/feed" url="https://a.example.com/"/>
+  <outline text="B" type="rss" url="https://b.example.com/feed"/>
+  <outline text="C" type="link" url="https://c.example.com/">
+    <outline text="D" xmlUrl="https://d.example.com/feed"/>
+  </outline>
+</body></opml>"#;
+        assert_eq!(
+            parse_opml(xml).unwrap(),
+            [
+                parsed("https://a.example.com/feed", "A", None, None),
+                parsed("https://b.example.com/feed", "B", None, None),
+                parsed("https://d.example.com/feed", "D", None, Some("C")),
+            ]
+        );
+    }
+
+    #[test]
+    fn clashing_names_are_all_kept() {
+        let xml = r#"<opml version="2.0"><body>
+  <outline text="Blog" xmlUrl="https://a.example.com/feed"/>
+  <outline text="blog" xmlUrl="https://b.example.com/feed"/>
+  <outline text="Blog" xmlUrl="https://a.example.com/feed"/>
+</body></opml>"#;
+        let feeds = parse_opml(xml).unwrap();
+        assert_eq!(feeds.len(), 3);
+        assert_eq!(feeds[1].text.as_deref(), Some("blog"));
+    }
+
+    #[test]
+    fn other_documents_are_refused() {
+        assert!(parse_opml("<rss><channel/></rss>").is_err());
+    }
+}
@@ -0,0 +1,34 @@
+use anyhow::Result;
+use colored::{ColoredString, Colorize};
+use html2text::render::RichAnnotation;
+
+/// Render an HTML fragment as wrapped, styled terminal text.
+///
+/// Headings, lists, quotes and code blocks are laid out by html2text; links
+/// become numbered references listed at the end.
+pub fn render_html(html: &str, width: usize) -> Result<String> {
+    let text =
+        html2text::config::rich()
+            .link_footnotes(true)
+            .coloured(html.as_bytes(), width, style)?;
+    Ok(text)
+}
+
+/// Map html2text's annotations for a run of text to terminal styles
+fn style(annotations: &[RichAnnotation], text: &str) -> String {
+    let mut styled: ColoredString = text.normal();
+
+    for a in annotations {
+        styled = match a {
+            RichAnnotation::Strong => styled.bold(),
+            RichAnnotation::Emphasis => styled.italic(),
+            RichAnnotation::Strikeout => styled.strikethrough(),
+            RichAnnotation::Code | RichAnnotation::Preformat(_) => styled.yellow(),
+            RichAnnotation::Link(_) => styled.blue().underline(),
+            RichAnnotation::Image(_) => styled.dimmed(),
+            _ => styled,
+        };
+    }
+
+    styled.to_string()
+}
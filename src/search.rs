@@ -0,0 +1,408 @@
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+use crate::state::Item;
+use crate::template::html_to_line;
+
+/// Something the text of an item must contain
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Word(String),
+    /// Words that must appear together, in order
+    Phrase(Vec<String>),
+}
+
+impl Term {
+    fn words(&self) -> &[String] {
+        match self {
+            Term::Word(w) => std::slice::from_ref(w),
+            Term::Phrase(words) => words,
+        }
+    }
+}
+
+/// A search as typed on the command line, e.g.
+/// `"async rust" tokio feed:rust after:2024-01-01`
+#[derive(Debug, Default)]
+pub struct ParsedQuery {
+    pub terms: Vec<Term>,
+    /// `feed:` qualifiers (alias/title/id/url); an item may be in any of them
+    pub feeds: Vec<String>,
+    /// `tag:` qualifiers; an item's feed may carry any of them
+    pub tags: Vec<String>,
+    /// `before:` date: only items dated before the start of that day
+    pub before: Option<DateTime<Utc>>,
+    /// `after:` date: only items dated on or after that day
+    pub after: Option<DateTime<Utc>>,
+}
+
+/// Which items to return from `Storage::search`
+#[derive(Debug, Default, Clone)]
+pub struct SearchQuery {
+    pub terms: Vec<Term>,
+    /// Only items from these feeds (`None` for every feed)
+    pub feed_ids: Option<Vec<String>>,
+    pub unread_only: bool,
+    pub before: Option<DateTime<Utc>>,
+    pub after: Option<DateTime<Utc>>,
+    /// Return at most this many items
+    pub limit: Option<usize>,
+    /// Skip this many of the items first, to page through them
+    pub offset: usize,
+}
+
+impl SearchQuery {
+    /// Whether an item that contains the terms passes the other filters
+    pub fn matches(&self, item: &Item) -> bool {
+        let feed_ok = self
+            .feed_ids
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&item.feed_id));
+        let date = item.date();
+        feed_ok
+            && (!self.unread_only || !item.read)
+            && self.before.is_none_or(|b| date < b)
+            && self.after.is_none_or(|a| date >= a)
+    }
+}
+
+/// Split a query into terms and qualifiers.
+///
+/// Double quotes make a phrase, and qualifier values can be quoted too
+/// (`feed:"Rust Blog"`). A bare word that splits into several words, like
+/// `rust-lang`, is matched as a phrase.
+pub fn parse_query(query: &str) -> Result<ParsedQuery> {
+    let mut parsed = ParsedQuery::default();
+    let mut chars = query.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            break;
+        };
+
+        // Read one token, treating quoted sections as part of it
+        let mut token = String::new();
+        let quoted = first == '"';
+        let mut in_quotes = false;
+        while let Some(&c) = chars.peek() {
+            if c == '"' {
+                in_quotes = !in_quotes;
+            } else if c.is_whitespace() && !in_quotes {
+                break;
+            } else {
+                token.push(c);
+            }
+            chars.next();
+        }
+
+        let qualifier = if quoted { None } else { token.split_once(':') };
+        match qualifier {
+            Some(("feed", value)) => parsed.feeds.push(value.to_string()),
+            Some(("tag", value)) => parsed.tags.push(value.to_string()),
+            Some(("before", value)) => parsed.before = Some(parse_day(value, "before")?),
+            Some(("after", value)) => parsed.after = Some(parse_day(value, "after")?),
+            _ => {
+                let mut words = words(&token);
+                match words.len() {
+                    0 => {}
+                    1 => parsed.terms.push(Term::Word(words.remove(0))),
+                    _ => parsed.terms.push(Term::Phrase(words)),
+                }
+            }
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Midnight UTC at the start of a YYYY-MM-DD day
+fn parse_day(value: &str, qualifier: &str) -> Result<DateTime<Utc>> {
+    let day = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .with_context(|| format!("Expected {qualifier}:YYYY-MM-DD, got '{value}'"))?;
+    match day.and_hms_opt(0, 0, 0) {
+        Some(t) => Ok(t.and_utc()),
+        None => bail!("Invalid date '{}'", value),
+    }
+}
+
+/// Lowercase words of a piece of plain text, with accents taken off
+/// ("Café" is "cafe")
+pub fn words(text: &str) -> Vec<String> {
+    let folded: String = text.nfd().filter(|c| !is_combining_mark(*c)).collect();
+    folded
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+/// An item's summary and content as plain text, for indexing
+pub fn body_text(item: &Item) -> String {
+    [&item.summary, &item.content]
+        .into_iter()
+        .flatten()
+        .map(|html| html_to_line(html))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The indexed words of one item
+#[derive(Debug, Serialize, Deserialize)]
+struct Doc {
+    /// Title words, an empty separator, then summary and content words
+    words: Vec<String>,
+    title_len: usize,
+}
+
+/// Version of the words and layout an index is built from; a saved index
+/// with another one is rebuilt
+const INDEX_VERSION: u32 = 2;
+
+/// Inverted index over item titles, summaries and content
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SearchIndex {
+    #[serde(default)]
+    version: u32,
+    /// `fingerprint` of the items as of the last `stamp`
+    #[serde(default)]
+    fingerprint: u64,
+    /// Item id -> its words
+    docs: HashMap<String, Doc>,
+    /// Word -> ids of items containing it
+    postings: HashMap<String, HashSet<String>>,
+    /// Words in all docs together, for their average length
+    #[serde(default)]
+    total_len: usize,
+}
+
+/// A hash of the text `SearchIndex` indexes for `items`, in order.
+///
+/// 64-bit FNV-1a, as in `state::item_id`; it only needs to notice when
+/// state.json was saved without the index that goes with it.
+fn fingerprint(items: &[Item]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for item in items {
+        let fields = [
+            Some(&item.id),
+            Some(&item.title),
+            item.summary.as_ref(),
+            item.content.as_ref(),
+        ];
+        for field in fields {
+            for b in field.map_or("", |f| f.as_str()).bytes().chain([0]) {
+                hash ^= b as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+    hash
+}
+
+impl SearchIndex {
+    pub fn build(items: &[Item]) -> SearchIndex {
+        let mut index = SearchIndex {
+            version: INDEX_VERSION,
+            ..SearchIndex::default()
+        };
+        for item in items {
+            index.add(item);
+        }
+        index.stamp(items);
+        index
+    }
+
+    /// Whether the index was stamped with exactly these items, and split
+    /// them into words the way `words` does now
+    pub fn covers(&self, items: &[Item]) -> bool {
+        self.version == INDEX_VERSION
+            && self.docs.len() == items.len()
+            && self.fingerprint == fingerprint(items)
+    }
+
+    /// Note that the index holds `items`, before it is saved alongside them
+    pub fn stamp(&mut self, items: &[Item]) {
+        self.fingerprint = fingerprint(items);
+    }
+
+    /// Index an item, replacing what was indexed for it before
+    pub fn add(&mut self, item: &Item) {
+        self.remove(&item.id);
+
+        let mut doc_words = words(&item.title);
+        let title_len = doc_words.len();
+        // Keeps phrases from matching across the title and body
+        doc_words.push(String::new());
+        doc_words.extend(words(&body_text(item)));
+
+        self.total_len += doc_words.len();
+        for w in doc_words.iter().filter(|w| !w.is_empty()) {
+            self.postings
+                .entry(w.clone())
+                .or_default()
+                .insert(item.id.clone());
+        }
+        self.docs.insert(
+            item.id.clone(),
+            Doc {
+                words: doc_words,
+                title_len,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        let Some(doc) = self.docs.remove(id) else {
+            return;
+        };
+        self.total_len -= doc.words.len();
+        for w in &doc.words {
+            if let Some(ids) = self.postings.get_mut(w) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.postings.remove(w);
+                }
+            }
+        }
+    }
+
+    /// Ids of items containing every term, best match first.
+    ///
+    /// Scored with BM25, counting a word in the title twice.
+    pub fn search(&self, terms: &[Term]) -> Vec<String> {
+        let query_words: Vec<&String> = terms.iter().flat_map(|t| t.words()).collect();
+        if query_words.is_empty() {
+            return Vec::new();
+        }
+
+        // Start from the rarest word's items and narrow down
+        let mut postings: Vec<&HashSet<String>> = Vec::new();
+        for w in &query_words {
+            match self.postings.get(*w) {
+                Some(ids) => postings.push(ids),
+                None => return Vec::new(),
+            }
+        }
+        postings.sort_by_key(|ids| ids.len());
+        let candidates = postings[0]
+            .iter()
+            .filter(|id| postings[1..].iter().all(|ids| ids.contains(*id)));
+
+        let n = self.docs.len() as f64;
+        let avg_len = self.total_len as f64 / n;
+        let (k1, b) = (1.2, 0.75);
+
+        let mut scored: Vec<(f64, &String)> = candidates
+            .filter_map(|id| {
+                let doc = &self.docs[id];
+                let phrases_ok = terms.iter().all(|t| match t {
+                    Term::Word(_) => true,
+                    Term::Phrase(p) => doc.words.windows(p.len()).any(|w| w == p.as_slice()),
+                });
+                if !phrases_ok {
+                    return None;
+                }
+
+                let len = doc.words.len() as f64;
+                let score: f64 = query_words
+                    .iter()
+                    .map(|w| {
+                        let df = self.postings[*w].len() as f64;
+                        let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+                        let tf = doc
+                            .words
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, dw)| dw == w)
+                            .map(|(i, _)| if i < doc.title_len { 2.0 } else { 1.0 })
+                            .sum::<f64>();
+                        idf * tf * (k1 + 1.0) / (tf + k1 * (1.0 - b + b * len / avg_len))
+                    })
+                    .sum();
+                Some((score, id))
+            })
+            .collect();
+
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.into_iter().map(|(_, id)| id.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, title: &str, summary: &str) -> Item {
+        let now = Utc::now();
+        Item {
+            id: id.to_string(),
+            feed_id: "news".to_string(),
+            guid: None,
+            title: title.to_string(),
+            link: format!("https://example.com/{id}"),
+            published_at: None,
+            updated_at: None,
+            summary: Some(summary.to_string()),
+            content: None,
+            author: None,
+            first_seen_at: now,
+            read: false,
+        }
+    }
+
+    fn search(index: &SearchIndex, query: &str) -> Vec<String> {
+        index.search(&parse_query(query).unwrap().terms)
+    }
+
+    #[test]
+    fn covers_only_the_items_it_was_stamped_with() {
+        let mut items = vec![
+            item("a", "Rust 1.80 released", "<p>New lints</p>"),
+            item("b", "Weekly roundup", "Café news"),
+        ];
+        let index = SearchIndex::build(&items);
+        assert!(index.covers(&items));
+
+        // Same ids, different text: say state.json was saved but not the
+        // index
+        items[0].title = "Rust 1.81 released".to_string();
+        assert!(!index.covers(&items));
+        items[0].title = "Rust 1.80 released".to_string();
+        items[1].summary = None;
+        assert!(!index.covers(&items));
+        assert!(!index.covers(&items[..1]));
+
+        let mut old = SearchIndex::build(&items);
+        old.version = INDEX_VERSION - 1;
+        assert!(!old.covers(&items));
+    }
+
+    #[test]
+    fn changes_keep_the_index_as_if_rebuilt() {
+        let a = item(
+            "a",
+            "Rust release",
+            "A long summary about the rust compiler",
+        );
+        let b = item("b", "Rust", "Short");
+        let c = item("c", "Python release", "Rust bindings");
+
+        let mut index = SearchIndex::build(&[a.clone(), b.clone()]);
+        index.add(&c);
+        index.add(&item("a", "Old title", "Gone"));
+        index.add(&a);
+        index.remove(&b.id);
+        index.remove("missing");
+
+        let rebuilt = SearchIndex::build(&[a, c]);
+        assert_eq!(index.total_len, rebuilt.total_len);
+        assert_eq!(index.docs.len(), 2);
+        assert_eq!(search(&index, "rust"), search(&rebuilt, "rust"));
+        assert!(search(&index, "old").is_empty());
+        assert_eq!(search(&index, "\"rust release\""), ["a"]);
+    }
+}
@@ -0,0 +1,707 @@
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use rusqlite::types::Value;
+use rusqlite::{Connection, OptionalExtension, Params, Row, params, params_from_iter};
+use std::fs;
+use std::fs::create_dir_all;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::lock::LockMode;
+use crate::search::{self, SearchQuery, Term};
+use crate::state::{self, Delivery, Feed, Item, State};
+use crate::storage::{ItemQuery, Storage};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS feeds (
+    url             TEXT PRIMARY KEY,
+    id              TEXT NOT NULL UNIQUE,
+    alias           TEXT,
+    title           TEXT,
+    added_at        TEXT NOT NULL,
+    last_fetched_at TEXT,
+    last_error      TEXT,
+    group_path      TEXT,
+    tags            TEXT NOT NULL DEFAULT '[]',
+    etag            TEXT,
+    last_modified   TEXT,
+    -- FeedOverrides as JSON
+    overrides       TEXT NOT NULL DEFAULT '{}',
+    -- RefreshHints as JSON
+    hints           TEXT NOT NULL DEFAULT '{}'
+);
+
+CREATE TABLE IF NOT EXISTS items (
+    id            TEXT PRIMARY KEY,
+    feed_id       TEXT NOT NULL,
+    guid          TEXT,
+    title         TEXT NOT NULL,
+    link          TEXT NOT NULL,
+    published_at  TEXT,
+    updated_at    TEXT,
+    summary       TEXT,
+    content       TEXT,
+    author        TEXT,
+    first_seen_at TEXT NOT NULL,
+    read          INTEGER NOT NULL DEFAULT 0,
+    -- Item::date() in unix millis, for newest-first listing
+    sort_at       INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS items_by_date ON items (sort_at DESC);
+CREATE INDEX IF NOT EXISTS items_by_feed_date ON items (feed_id, sort_at DESC);
+
+-- Full-text index over item titles, summaries and content, as the words
+-- search::words splits them into. Its rowids are those of the items rows.
+CREATE VIRTUAL TABLE IF NOT EXISTS items_fts USING fts5(
+    title, body, tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS items_fts_delete AFTER DELETE ON items BEGIN
+    DELETE FROM items_fts WHERE rowid = old.rowid;
+END;
+
+-- Odds and ends that aren't feeds or items, e.g. last_viewed_at
+CREATE TABLE IF NOT EXISTS meta (
+    key   TEXT PRIMARY KEY,
+    value TEXT
+);
+
+-- Webhook delivery log; rowid order is log order
+CREATE TABLE IF NOT EXISTS deliveries (
+    webhook         TEXT NOT NULL,
+    item_id         TEXT NOT NULL,
+    item_title      TEXT NOT NULL,
+    created_at      TEXT NOT NULL,
+    attempts        INTEGER NOT NULL DEFAULT 0,
+    last_attempt_at TEXT,
+    delivered_at    TEXT,
+    last_error      TEXT,
+    PRIMARY KEY (webhook, item_id)
+);
+";
+
+/// `user_version` of a database with every upgrade in `open` applied
+const USER_VERSION: u32 = 4;
+
+/// Open (creating if needed) the database and make sure the schema exists.
+///
+/// Under a shared lock, a database made by an older release is a
+/// `NeedsExclusiveLock` error rather than being upgraded.
+fn open(path: &Path, mode: LockMode) -> Result<Connection> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut conn = Connection::open(path)
+        .with_context(|| format!("Could not open database {}", path.display()))?;
+
+    let version: u32 = conn.pragma_query_value(None, "user_version", |r| r.get(0))?;
+    if version < USER_VERSION && mode == LockMode::Shared {
+        return Err(state::NeedsExclusiveLock.into());
+    }
+    conn.execute_batch(SCHEMA)?;
+
+    // Databases made before search existed have items but no index for them
+    if version < 1 {
+        index_all_items(&mut conn)?;
+        conn.pragma_update(None, "user_version", 1)?;
+    }
+
+    // Per-feed overrides and refresh hints came after the feeds table
+    if version < 2 {
+        add_column_if_missing(&conn, "feeds", "overrides", "TEXT NOT NULL DEFAULT '{}'")?;
+        conn.pragma_update(None, "user_version", 2)?;
+    }
+    if version < 3 {
+        add_column_if_missing(&conn, "feeds", "hints", "TEXT NOT NULL DEFAULT '{}'")?;
+        conn.pragma_update(None, "user_version", 3)?;
+    }
+
+    // The index used to hold text as-is, with accents, so searches without
+    // them found nothing
+    if version < 4 {
+        conn.execute_batch("DROP TABLE items_fts;")?;
+        conn.execute_batch(SCHEMA)?;
+        index_all_items(&mut conn)?;
+        conn.pragma_update(None, "user_version", 4)?;
+    }
+
+    Ok(conn)
+}
+
+/// Add a column to a table made by an older release; new tables already
+/// have it
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        [table, column],
+        |r| r.get(0),
+    )?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// Fill the full-text index from scratch
+fn index_all_items(conn: &mut Connection) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM items_fts", [])?;
+
+    let rows = tx
+        .prepare("SELECT rowid, * FROM items")?
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>("rowid")?, item_from_row(row)?))
+        })?
+        .collect::<rusqlite::Result<Vec<(i64, Item)>>>()?;
+    for (rowid, item) in &rows {
+        index_item(&tx, *rowid, item)?;
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+/// Feeds and items in a SQLite database.
+///
+/// Every change is written as it is made, so `save` has nothing left to do.
+/// Feeds are few and read constantly, so they are also cached in memory.
+pub struct SqliteStore {
+    conn: Connection,
+    feeds: Vec<Feed>,
+}
+
+impl SqliteStore {
+    /// Open the configured database.
+    ///
+    /// The first time the database is used, an existing state.json is
+    /// imported into it and renamed to state.json.migrated, so it isn't used
+    /// again. Creating the database needs an exclusive lock.
+    pub fn open(cfg: &Config, mode: LockMode) -> Result<SqliteStore> {
+        let fresh = !cfg.database_path.exists();
+        if fresh && mode == LockMode::Shared {
+            return Err(state::NeedsExclusiveLock.into());
+        }
+
+        // Read state.json before the database exists, so if it can't be
+        // read, the next run still sees a fresh database and imports it
+        let imported = if fresh && cfg.state_path.exists() {
+            Some(state::load_json_state(&cfg.state_path, mode)?)
+        } else {
+            None
+        };
+
+        let mut conn = open(&cfg.database_path, mode)?;
+        if let Some(state) = imported {
+            migrate_from_json(&mut conn, cfg, state)?;
+        }
+
+        let feeds = conn
+            .prepare("SELECT * FROM feeds ORDER BY rowid")?
+            .query_map([], feed_from_row)?
+            .collect::<rusqlite::Result<Vec<Feed>>>()?;
+
+        Ok(SqliteStore { conn, feeds })
+    }
+
+    fn query_items(&self, sql: &str, params: impl Params) -> Result<Vec<Item>> {
+        let items = self
+            .conn
+            .prepare(sql)?
+            .query_map(params, item_from_row)?
+            .collect::<rusqlite::Result<Vec<Item>>>()?;
+        Ok(items)
+    }
+}
+
+impl Storage for SqliteStore {
+    fn feeds(&self) -> &[Feed] {
+        &self.feeds
+    }
+
+    fn add_feed(&mut self, feed: Feed) -> Result<()> {
+        if self
+            .feeds
+            .iter()
+            .any(|f| f.url == feed.url || f.id == feed.id)
+        {
+            bail!("Feed already exists");
+        }
+        insert_feed(&self.conn, &feed)?;
+        self.feeds.push(feed);
+        Ok(())
+    }
+
+    fn update_feed(&mut self, feed: &Feed) -> Result<()> {
+        let Some(stored) = self.feeds.iter_mut().find(|f| f.url == feed.url) else {
+            bail!("No feed with url {}", feed.url);
+        };
+        if stored == feed {
+            return Ok(());
+        }
+
+        let tx = self.conn.transaction()?;
+        update_feed(&tx, feed)?;
+        if stored.id != feed.id {
+            tx.execute(
+                "UPDATE items SET feed_id = ?1 WHERE feed_id = ?2",
+                params![feed.id, stored.id],
+            )?;
+        }
+        tx.commit()?;
+
+        *stored = feed.clone();
+        Ok(())
+    }
+
+    fn remove_feed(&mut self, key: &str) -> Result<usize> {
+        let removed: Vec<Feed> = self
+            .feeds
+            .iter()
+            .filter(|f| f.matches_key(key))
+            .cloned()
+            .collect();
+
+        let tx = self.conn.transaction()?;
+        for feed in &removed {
+            tx.execute("DELETE FROM items WHERE feed_id = ?1", [&feed.id])?;
+            tx.execute("DELETE FROM feeds WHERE url = ?1", [&feed.url])?;
+        }
+        tx.commit()?;
+
+        self.feeds.retain(|f| !f.matches_key(key));
+        Ok(removed.len())
+    }
+
+    fn items(&self, query: &ItemQuery) -> Result<Vec<Item>> {
+        let mut sql = String::from("SELECT * FROM items WHERE 1");
+        let mut values: Vec<Value> = Vec::new();
+
+        if let Some(ids) = &query.feed_ids {
+            if ids.is_empty() {
+                return Ok(Vec::new());
+            }
+            let marks = vec!["?"; ids.len()].join(", ");
+            sql.push_str(&format!(" AND feed_id IN ({marks})"));
+            values.extend(ids.iter().cloned().map(Value::Text));
+        }
+        if query.unread_only {
+            sql.push_str(" AND read = 0");
+        }
+        if let Some(since) = query.since {
+            sql.push_str(" AND sort_at >= ?");
+            values.push(Value::Integer(since.timestamp_millis()));
+        }
+        if let Some(until) = query.until {
+            sql.push_str(" AND sort_at < ?");
+            values.push(Value::Integer(until.timestamp_millis()));
+        }
+        if let Some(seen_after) = query.seen_after {
+            sql.push_str(" AND first_seen_at > ?");
+            // Timestamps are stored as text that sorts in time order
+            values.push(Value::Text(seen_after.format("%F %T%.f%:z").to_string()));
+        }
+        // Ties go by rowid (as `items_by_date` has them), so pages of the
+        // same query don't overlap
+        sql.push_str(" ORDER BY sort_at DESC, rowid");
+        push_page(&mut sql, query.limit, query.offset);
+
+        self.query_items(&sql, params_from_iter(values))
+    }
+
+    fn find_item(&self, key: &str) -> Result<Item> {
+        let exact = self.query_items(
+            "SELECT * FROM items WHERE id = ?1 OR guid = ?1 OR link = ?1 LIMIT 1",
+            [key],
+        )?;
+        if let Some(item) = exact.into_iter().next() {
+            return Ok(item);
+        }
+
+        let key_lower = key.to_lowercase();
+        let matches = if key.is_empty() {
+            Vec::new()
+        } else {
+            self.query_items(
+                "SELECT * FROM items WHERE substr(id, 1, length(?1)) = ?1",
+                [&key_lower],
+            )?
+        };
+
+        match matches.len() {
+            0 => bail!("No matching item for '{}'", key),
+            1 => Ok(matches.into_iter().next().expect("one match")),
+            n => bail!(
+                "Item id '{}' is ambiguous ({} matches); use more characters",
+                key,
+                n
+            ),
+        }
+    }
+
+    fn search(&self, query: &SearchQuery) -> Result<Vec<Item>> {
+        // Terms are already split into plain words, so quoting each one (or
+        // each phrase) is enough to keep FTS5 from reading any as syntax
+        let terms: Vec<String> = query
+            .terms
+            .iter()
+            .map(|t| match t {
+                Term::Word(w) => format!("\"{w}\""),
+                Term::Phrase(words) => format!("\"{}\"", words.join(" ")),
+            })
+            .collect();
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut sql = String::from(
+            "SELECT items.* FROM items_fts JOIN items ON items.rowid = items_fts.rowid
+             WHERE items_fts MATCH ?",
+        );
+        let mut values: Vec<Value> = vec![Value::Text(terms.join(" "))];
+
+        if let Some(ids) = &query.feed_ids {
+            if ids.is_empty() {
+                return Ok(Vec::new());
+            }
+            let marks = vec!["?"; ids.len()].join(", ");
+            sql.push_str(&format!(" AND items.feed_id IN ({marks})"));
+            values.extend(ids.iter().cloned().map(Value::Text));
+        }
+        if query.unread_only {
+            sql.push_str(" AND items.read = 0");
+        }
+        if let Some(before) = query.before {
+            sql.push_str(" AND items.sort_at < ?");
+            values.push(Value::Integer(before.timestamp_millis()));
+        }
+        if let Some(after) = query.after {
+            sql.push_str(" AND items.sort_at >= ?");
+            values.push(Value::Integer(after.timestamp_millis()));
+        }
+        // Title matches count twice, like the JSON store's index
+        sql.push_str(" ORDER BY bm25(items_fts, 2.0, 1.0), items.rowid");
+        push_page(&mut sql, query.limit, query.offset);
+
+        self.query_items(&sql, params_from_iter(values))
+    }
+
+    fn upsert_items(&mut self, feed_id: &str, items: Vec<Item>) -> Result<Vec<Item>> {
+        // Merge against the feed's stored history with the same rules as the
+        // JSON store, then write back only the rows that changed
+        let before = self.query_items("SELECT * FROM items WHERE feed_id = ?1", [feed_id])?;
+        let mut merged = State {
+            items: before.clone(),
+            ..State::default()
+        };
+        let added = merged.merge_items(feed_id, items);
+
+        let tx = self.conn.transaction()?;
+        for (i, item) in merged.items.iter().enumerate() {
+            if before.get(i) != Some(item) {
+                upsert_item(&tx, item)?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(added)
+    }
+
+    fn mark_read(&mut self, ids: &[String]) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut count = 0;
+        for id in ids {
+            count += tx.execute("UPDATE items SET read = 1 WHERE id = ?1 AND read = 0", [id])?;
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    fn trim_history(&mut self, feed_id: &str, max: usize) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM items WHERE feed_id = ?1 AND id NOT IN
+                (SELECT id FROM items WHERE feed_id = ?1 ORDER BY sort_at DESC LIMIT ?2)",
+            params![feed_id, max as i64],
+        )?;
+        Ok(())
+    }
+
+    fn deliveries(&self) -> Result<Vec<Delivery>> {
+        let deliveries = self
+            .conn
+            .prepare("SELECT * FROM deliveries ORDER BY rowid")?
+            .query_map([], delivery_from_row)?
+            .collect::<rusqlite::Result<Vec<Delivery>>>()?;
+        Ok(deliveries)
+    }
+
+    fn save_delivery(&mut self, delivery: &Delivery) -> Result<()> {
+        save_delivery(&self.conn, delivery)
+    }
+
+    fn trim_deliveries(&mut self, max: usize) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM deliveries WHERE rowid NOT IN
+                (SELECT rowid FROM deliveries ORDER BY rowid DESC LIMIT ?1)",
+            [max as i64],
+        )?;
+        Ok(())
+    }
+
+    fn last_viewed_at(&self) -> Result<Option<DateTime<Utc>>> {
+        meta_time(&self.conn, "last_viewed_at")
+    }
+
+    fn previous_viewed_at(&self) -> Result<Option<DateTime<Utc>>> {
+        meta_time(&self.conn, "previous_viewed_at")
+    }
+
+    fn set_last_viewed_at(&mut self, at: DateTime<Utc>) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM meta WHERE key = 'previous_viewed_at'", [])?;
+        tx.execute(
+            "UPDATE meta SET key = 'previous_viewed_at' WHERE key = 'last_viewed_at'",
+            [],
+        )?;
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES ('last_viewed_at', ?1)",
+            [at],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// One-way import of an existing state.json into a new database
+fn migrate_from_json(conn: &mut Connection, cfg: &Config, state: State) -> Result<()> {
+    let tx = conn.transaction()?;
+    for feed in &state.feeds {
+        insert_feed(&tx, feed)?;
+    }
+    for item in &state.items {
+        upsert_item(&tx, item)?;
+    }
+    for delivery in &state.deliveries {
+        save_delivery(&tx, delivery)?;
+    }
+    for (key, at) in [
+        ("last_viewed_at", state.last_viewed_at),
+        ("previous_viewed_at", state.previous_viewed_at),
+    ] {
+        if let Some(at) = at {
+            tx.execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+                params![key, at],
+            )?;
+        }
+    }
+    tx.commit()?;
+
+    let mut migrated = cfg.state_path.clone().into_os_string();
+    migrated.push(".migrated");
+    fs::rename(&cfg.state_path, &migrated)?;
+
+    eprintln!(
+        "Migrated {} feed(s) and {} item(s) from {} to {}",
+        state.feeds.len(),
+        state.items.len(),
+        cfg.state_path.display(),
+        cfg.database_path.display()
+    );
+    Ok(())
+}
+
+fn feed_from_row(row: &Row) -> rusqlite::Result<Feed> {
+    let tags: String = row.get("tags")?;
+    let overrides: String = row.get("overrides")?;
+    let hints: String = row.get("hints")?;
+    Ok(Feed {
+        id: row.get("id")?,
+        url: row.get("url")?,
+        alias: row.get("alias")?,
+        title: row.get("title")?,
+        added_at: row.get("added_at")?,
+        last_fetched_at: row.get("last_fetched_at")?,
+        last_error: row.get("last_error")?,
+        group: row.get("group_path")?,
+        tags: serde_json::from_str(&tags).unwrap_or_default(),
+        etag: row.get("etag")?,
+        last_modified: row.get("last_modified")?,
+        overrides: serde_json::from_str(&overrides).unwrap_or_default(),
+        hints: serde_json::from_str(&hints).unwrap_or_default(),
+    })
+}
+
+fn item_from_row(row: &Row) -> rusqlite::Result<Item> {
+    Ok(Item {
+        id: row.get("id")?,
+        feed_id: row.get("feed_id")?,
+        guid: row.get("guid")?,
+        title: row.get("title")?,
+        link: row.get("link")?,
+        published_at: row.get("published_at")?,
+        updated_at: row.get("updated_at")?,
+        summary: row.get("summary")?,
+        content: row.get("content")?,
+        author: row.get("author")?,
+        first_seen_at: row.get("first_seen_at")?,
+        read: row.get("read")?,
+    })
+}
+
+fn delivery_from_row(row: &Row) -> rusqlite::Result<Delivery> {
+    Ok(Delivery {
+        webhook: row.get("webhook")?,
+        item_id: row.get("item_id")?,
+        item_title: row.get("item_title")?,
+        created_at: row.get("created_at")?,
+        attempts: row.get("attempts")?,
+        last_attempt_at: row.get("last_attempt_at")?,
+        delivered_at: row.get("delivered_at")?,
+        last_error: row.get("last_error")?,
+    })
+}
+
+fn insert_feed(conn: &Connection, f: &Feed) -> Result<()> {
+    conn.execute(
+        "INSERT INTO feeds (url, id, alias, title, added_at, last_fetched_at, last_error,
+                            group_path, tags, etag, last_modified, overrides, hints)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![
+            f.url,
+            f.id,
+            f.alias,
+            f.title,
+            f.added_at,
+            f.last_fetched_at,
+            f.last_error,
+            f.group,
+            serde_json::to_string(&f.tags)?,
+            f.etag,
+            f.last_modified,
+            serde_json::to_string(&f.overrides)?,
+            serde_json::to_string(&f.hints)?,
+        ],
+    )?;
+    Ok(())
+}
+
+fn update_feed(conn: &Connection, f: &Feed) -> Result<()> {
+    conn.execute(
+        "UPDATE feeds SET id = ?2, alias = ?3, title = ?4, added_at = ?5, last_fetched_at = ?6,
+                          last_error = ?7, group_path = ?8, tags = ?9, etag = ?10,
+                          last_modified = ?11, overrides = ?12, hints = ?13
+         WHERE url = ?1",
+        params![
+            f.url,
+            f.id,
+            f.alias,
+            f.title,
+            f.added_at,
+            f.last_fetched_at,
+            f.last_error,
+            f.group,
+            serde_json::to_string(&f.tags)?,
+            f.etag,
+            f.last_modified,
+            serde_json::to_string(&f.overrides)?,
+            serde_json::to_string(&f.hints)?,
+        ],
+    )?;
+    Ok(())
+}
+
+fn upsert_item(conn: &Connection, i: &Item) -> Result<()> {
+    // Replacing a row gives it a new rowid, without firing the delete trigger
+    conn.execute(
+        "DELETE FROM items_fts WHERE rowid = (SELECT rowid FROM items WHERE id = ?1)",
+        [&i.id],
+    )?;
+
+    conn.execute(
+        "INSERT OR REPLACE INTO items (id, feed_id, guid, title, link, published_at, updated_at,
+                                       summary, content, author, first_seen_at, read, sort_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![
+            i.id,
+            i.feed_id,
+            i.guid,
+            i.title,
+            i.link,
+            i.published_at,
+            i.updated_at,
+            i.summary,
+            i.content,
+            i.author,
+            i.first_seen_at,
+            i.read,
+            i.date().timestamp_millis(),
+        ],
+    )?;
+
+    index_item(conn, conn.last_insert_rowid(), i)
+}
+
+/// Index an item's words, so they match query terms exactly as they do in
+/// the JSON store's index
+fn index_item(conn: &Connection, rowid: i64, i: &Item) -> Result<()> {
+    conn.execute(
+        "INSERT INTO items_fts (rowid, title, body) VALUES (?1, ?2, ?3)",
+        params![
+            rowid,
+            search::words(&i.title).join(" "),
+            search::words(&search::body_text(i)).join(" ")
+        ],
+    )?;
+    Ok(())
+}
+
+/// Add the `LIMIT`/`OFFSET` for one page of a query
+fn push_page(sql: &mut String, limit: Option<usize>, offset: usize) {
+    match (limit, offset) {
+        (Some(limit), offset) => sql.push_str(&format!(" LIMIT {limit} OFFSET {offset}")),
+        (None, 0) => {}
+        (None, offset) => sql.push_str(&format!(" LIMIT -1 OFFSET {offset}")),
+    }
+}
+
+/// A time kept in the meta table
+fn meta_time(conn: &Connection, key: &str) -> Result<Option<DateTime<Utc>>> {
+    let at = conn
+        .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    Ok(at)
+}
+
+/// Insert a log entry, or update the one for the same webhook and item in
+/// place (keeping its position in the log)
+fn save_delivery(conn: &Connection, d: &Delivery) -> Result<()> {
+    conn.execute(
+        "INSERT INTO deliveries (webhook, item_id, item_title, created_at, attempts,
+                                 last_attempt_at, delivered_at, last_error)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT (webhook, item_id) DO UPDATE SET
+             item_title = ?3, created_at = ?4, attempts = ?5, last_attempt_at = ?6,
+             delivered_at = ?7, last_error = ?8",
+        params![
+            d.webhook,
+            d.item_id,
+            d.item_title,
+            d.created_at,
+            d.attempts,
+            d.last_attempt_at,
+            d.delivered_at,
+            d.last_error,
+        ],
+    )?;
+    Ok(())
+}
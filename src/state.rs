@@ -1,14 +1,19 @@
-use anyhow::Result;
-use chrono::{DateTime, Utc};
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error as StdError;
+use std::fmt;
 use std::fs;
-use std::fs::create_dir_all;
-use std::path::Path;
+use std::fs::{File, create_dir_all};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 
-use crate::config::Config;
+use crate::lock::LockMode;
 
 /// A subscribed feed
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Feed {
     pub id: String, // auto-generated id (or alias if set)
     pub url: String,
@@ -17,96 +22,738 @@ pub struct Feed {
     pub added_at: DateTime<Utc>,
     pub last_fetched_at: Option<DateTime<Utc>>,
     pub last_error: Option<String>,
+    /// Group path (e.g. "work/rust"), set from nested OPML outlines
+    pub group: Option<String>,
+    /// Free-form lowercase tags, e.g. "rust", "work"
+    pub tags: BTreeSet<String>,
+    /// HTTP validators from the last successful fetch, for conditional GET
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Settings this feed overrides, set with `rsso set`
+    pub overrides: FeedOverrides,
+    /// How often the feed asks to be fetched, as of the last fetch
+    pub hints: RefreshHints,
+}
+
+/// Per-feed replacements for config settings; unset fields use the config
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct FeedOverrides {
+    /// Minutes between refreshes, instead of `refresh_age_mins`
+    pub refresh_mins: Option<u64>,
+    /// Items to keep, instead of `max_history_per_feed`
+    pub max_history: Option<usize>,
+    /// Items `rsso feed` shows, instead of `default_limit`
+    pub limit: Option<usize>,
+    /// Name shown in listings, instead of the alias or title
+    pub label: Option<String>,
+    /// Disabled feeds aren't refreshed or shown in the combined listing
+    pub disabled: bool,
+}
+
+/// How often a feed says it may be fetched, from the feed itself and the
+/// caching headers of the last response
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct RefreshHints {
+    /// RSS `<ttl>`: minutes the feed may be cached for
+    pub ttl_mins: Option<u64>,
+    /// Minutes between updates, from `sy:updatePeriod`/`sy:updateFrequency`
+    pub update_interval_mins: Option<u64>,
+    /// RSS `<skipHours>`: hours of the day (UTC) not to fetch in
+    pub skip_hours: BTreeSet<u32>,
+    /// RSS `<skipDays>`: days (UTC) not to fetch on, 0 = Monday
+    pub skip_days: BTreeSet<u32>,
+    /// When the last response stops being fresh, from `Cache-Control:
+    /// max-age` or `Expires`
+    pub fresh_until: Option<DateTime<Utc>>,
+}
+
+/// Longest a feed's hints may hold off a refresh, so a feed that asks for a
+/// month (or is misconfigured) is still checked weekly
+pub const MAX_HINT_MINS: u64 = 7 * 24 * 60;
+
+impl RefreshHints {
+    /// How long after a fetch the feed asks to be left alone
+    pub fn min_interval(&self) -> Option<Duration> {
+        self.ttl_mins
+            .max(self.update_interval_mins)
+            .map(|mins| Duration::minutes(mins.min(MAX_HINT_MINS) as i64))
+    }
+
+    /// Whether `t` falls in an hour or on a day the feed asks us to skip
+    pub fn skips(&self, t: DateTime<Utc>) -> bool {
+        self.skip_hours.contains(&t.hour())
+            || self.skip_days.contains(&t.weekday().num_days_from_monday())
+    }
 }
 
 /// A single item/article in a feed
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Item {
+    /// Short stable id derived from the feed URL and item key (see `item_id`)
+    pub id: String,
     pub feed_id: String,
+    /// The entry's id/guid from the feed, if it declared one
+    pub guid: Option<String>,
     pub title: String,
     pub link: String,
     pub published_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
     pub summary: Option<String>,
+    /// Full HTML body, when the feed includes one
+    pub content: Option<String>,
+    pub author: Option<String>,
     pub first_seen_at: DateTime<Utc>,
+    pub read: bool,
+}
+
+/// How many characters of an item id are shown in listings
+pub const SHORT_ID_LEN: usize = 8;
+
+/// Derive a stable item id from its feed's URL and its `Item::key`.
+///
+/// This is a 64-bit FNV-1a hash in hex; it only needs to be stable across
+/// runs and platforms, not cryptographically strong. FNV mixes the last few
+/// bytes poorly into the high bits (which the short id shows), so the result
+/// goes through a final avalanche step.
+pub fn item_id(feed_url: &str, key: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in feed_url.bytes().chain([0]).chain(key.bytes()) {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    // MurmurHash3's fmix64 finalizer
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^= hash >> 33;
+
+    format!("{hash:016x}")
+}
+
+impl Feed {
+    /// Match by alias OR title (case-insensitive),
+    /// fallback to exact id/url match.
+    pub fn matches_key(&self, key: &str) -> bool {
+        let key_lower = key.to_lowercase();
+
+        // Alias match
+        if let Some(alias) = &self.alias
+            && alias.to_lowercase() == key_lower
+        {
+            return true;
+        }
+
+        // Title match
+        if let Some(title) = &self.title
+            && title.to_lowercase() == key_lower
+        {
+            return true;
+        }
+
+        // Fallback: exact match on id or url
+        if self.id == key || self.url == key {
+            return true;
+        }
+
+        false
+    }
+}
+
+impl Item {
+    /// The id prefix shown in listings and accepted on the command line
+    pub fn short_id(&self) -> &str {
+        &self.id[..SHORT_ID_LEN.min(self.id.len())]
+    }
+
+    /// Stable identity used to recognise an item across refreshes:
+    /// the entry's guid, falling back to its link (or title if it has none)
+    pub fn key(&self) -> &str {
+        match &self.guid {
+            Some(g) => g,
+            None if !self.link.is_empty() => &self.link,
+            None => &self.title,
+        }
+    }
+
+    /// Best known date for the item: published, then updated, then first seen
+    pub fn date(&self) -> DateTime<Utc> {
+        self.published_at
+            .unwrap_or(self.updated_at.unwrap_or(self.first_seen_at))
+    }
+}
+
+/// One item's delivery to one webhook, kept as a log entry
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Delivery {
+    /// URL of the webhook, as configured
+    pub webhook: String,
+    pub item_id: String,
+    /// Kept so the log still reads well once the item is trimmed
+    pub item_title: String,
+    pub created_at: DateTime<Utc>,
+    /// HTTP requests made so far
+    pub attempts: u32,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+impl Delivery {
+    /// A new, not yet attempted delivery of `item`
+    pub fn new(webhook: &str, item: &Item, now: DateTime<Utc>) -> Delivery {
+        Delivery {
+            webhook: webhook.to_string(),
+            item_id: item.id.clone(),
+            item_title: item.title.clone(),
+            created_at: now,
+            attempts: 0,
+            last_attempt_at: None,
+            delivered_at: None,
+            last_error: None,
+        }
+    }
 }
 
 /// Entire app state that gets serialized to JSON
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct State {
+    /// Layout version of the file; always `SCHEMA_VERSION` once loaded
+    pub schema_version: u32,
     pub feeds: Vec<Feed>,
     pub items: Vec<Item>,
+    /// Webhook delivery log, oldest first
+    pub deliveries: Vec<Delivery>,
+    /// When items were last listed on a terminal, for `--since last-run`
+    pub last_viewed_at: Option<DateTime<Utc>>,
+    /// The `last_viewed_at` before that: where the last listing's
+    /// `--since last-run` started, so `open` can refer back to it
+    pub previous_viewed_at: Option<DateTime<Utc>>,
 }
 
-/// Load state from JSON (or create an empty one)
-pub fn load_state(cfg: &Config) -> Result<State> {
-    let path = &cfg.state_path;
-    if !Path::new(path).exists() {
-        if let Some(parent) = path.parent() {
-            create_dir_all(parent)?;
+impl Default for State {
+    fn default() -> Self {
+        State {
+            schema_version: SCHEMA_VERSION,
+            feeds: Vec::new(),
+            items: Vec::new(),
+            deliveries: Vec::new(),
+            last_viewed_at: None,
+            previous_viewed_at: None,
         }
-        return Ok(State::default());
     }
+}
 
-    let contents = fs::read_to_string(path)?;
-    if contents.trim().is_empty() {
-        return Ok(State::default());
+/// Version of the state file layout this build reads and writes.
+///
+/// Bump it whenever `State`, `Feed` or `Item` change shape, and add a step to
+/// `MIGRATIONS` that upgrades documents from the previous version.
+pub const SCHEMA_VERSION: u32 = 6;
+
+/// One step of the migration pipeline, upgrading a state document from
+/// version `to - 1` to `to`. `apply` returns a line for each kind of change
+/// it made, for `rsso state migrate` to show.
+struct Migration {
+    to: u32,
+    description: &'static str,
+    apply: fn(&mut Map<String, Value>) -> Vec<String>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        to: 1,
+        description: "add schema_version and fill in fields older releases left out",
+        apply: migrate_v0_to_v1,
+    },
+    Migration {
+        to: 2,
+        description: "add the webhook delivery log",
+        apply: migrate_v1_to_v2,
+    },
+    Migration {
+        to: 3,
+        description: "record when items were last viewed",
+        apply: migrate_v2_to_v3,
+    },
+    Migration {
+        to: 4,
+        description: "add per-feed setting overrides",
+        apply: migrate_v3_to_v4,
+    },
+    Migration {
+        to: 5,
+        description: "add refresh hints declared by feeds",
+        apply: migrate_v4_to_v5,
+    },
+    Migration {
+        to: 6,
+        description: "record where the last listing since the last run started",
+        apply: migrate_v5_to_v6,
+    },
+];
+
+/// A migration step that was applied to a document
+#[derive(Debug)]
+pub struct AppliedMigration {
+    pub to: u32,
+    pub description: &'static str,
+    pub changes: Vec<String>,
+}
+
+/// What `migrate_document` did to a state document
+#[derive(Debug)]
+pub struct MigrationReport {
+    /// Schema version the document had before migrating
+    pub from: u32,
+    pub applied: Vec<AppliedMigration>,
+}
+
+/// The state file was written by a newer rsso than this one
+#[derive(Debug)]
+pub struct NewerSchemaError {
+    pub found: u32,
+}
+
+impl fmt::Display for NewerSchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "State file has schema version {}, but this rsso only understands up to {}; \
+             upgrade rsso to use it",
+            self.found, SCHEMA_VERSION
+        )
     }
+}
+
+impl StdError for NewerSchemaError {}
 
-    let state: State = serde_json::from_str(&contents)?;
-    Ok(state)
+/// The state has to be upgraded or recovered before it can be used, which
+/// can't be done under a shared lock
+#[derive(Debug)]
+pub struct NeedsExclusiveLock;
+
+impl fmt::Display for NeedsExclusiveLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The state needs upgrading or recovering, which needs an exclusive lock"
+        )
+    }
 }
 
-/// Save state to JSON
-pub fn save_state(cfg: &Config, state: &State) -> Result<()> {
-    let path = &cfg.state_path;
-    if let Some(parent) = path.parent() {
-        create_dir_all(parent)?;
+impl StdError for NeedsExclusiveLock {}
+
+/// The state file can't be read, but has a backup to recover from
+#[derive(Debug)]
+pub struct CorruptStateError {
+    pub path: PathBuf,
+    pub backup: PathBuf,
+    /// Why the state file couldn't be read
+    pub reason: String,
+}
+
+impl CorruptStateError {
+    /// Ask on the terminal whether to recover from the backup; no if there's
+    /// no terminal to ask on
+    pub fn ask_to_recover(&self) -> Result<bool> {
+        if !io::stdin().is_terminal() {
+            return Ok(false);
+        }
+        confirm(&format!(
+            "{} is corrupt ({}).\nRecover from {}? [y/N] ",
+            self.path.display(),
+            self.reason,
+            self.backup.display()
+        ))
     }
-    let json = serde_json::to_string_pretty(state)?;
-    fs::write(path, json)?;
-    Ok(())
 }
 
-impl State {
-    /// Match by alias OR title (case-insensitive),
-    /// fallback to exact id/url match.
-    fn feed_matches(f: &Feed, key: &str) -> bool {
-        let key_lower = key.to_lowercase();
+impl fmt::Display for CorruptStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Could not read {}: {} (a backup is at {})",
+            self.path.display(),
+            self.reason,
+            self.backup.display()
+        )
+    }
+}
 
-        // Alias match
-        if let Some(alias) = &f.alias {
-            if alias.to_lowercase() == key_lower {
-                return true;
+impl StdError for CorruptStateError {}
+
+/// Upgrade a raw state document to `SCHEMA_VERSION`, one step at a time.
+///
+/// Files written before versioning have no `schema_version` and count as
+/// version 0.
+pub fn migrate_document(doc: &mut Value) -> Result<MigrationReport> {
+    let Some(obj) = doc.as_object_mut() else {
+        bail!("State file is not a JSON object");
+    };
+
+    let from = match obj.get("schema_version") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .context("schema_version is not a version number")?,
+    };
+    if from > SCHEMA_VERSION {
+        return Err(NewerSchemaError { found: from }.into());
+    }
+
+    let mut applied = Vec::new();
+    for m in MIGRATIONS.iter().filter(|m| m.to > from) {
+        let changes = (m.apply)(obj);
+        obj.insert("schema_version".to_string(), m.to.into());
+        applied.push(AppliedMigration {
+            to: m.to,
+            description: m.description,
+            changes,
+        });
+    }
+
+    Ok(MigrationReport { from, applied })
+}
+
+/// Version 0 -> 1.
+///
+/// Groups, tags, HTTP validators, item ids, guids, content, authors and read
+/// flags were added one release at a time and used to be filled in by serde
+/// defaults. Write them out explicitly, and give every item its id.
+fn migrate_v0_to_v1(doc: &mut Map<String, Value>) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    let mut feeds_filled = 0;
+    let mut urls: HashMap<String, String> = HashMap::new();
+    if let Some(Value::Array(feeds)) = doc.get_mut("feeds") {
+        for feed in feeds.iter_mut().filter_map(Value::as_object_mut) {
+            let mut filled = fill_missing(feed, &["group", "etag", "last_modified"], Value::Null);
+            filled |= fill_missing(feed, &["tags"], Value::Array(Vec::new()));
+            feeds_filled += filled as usize;
+
+            if let (Some(id), Some(url)) = (
+                feed.get("id").and_then(Value::as_str),
+                feed.get("url").and_then(Value::as_str),
+            ) {
+                urls.insert(id.to_string(), url.to_string());
             }
         }
+    }
 
-        // Title match
-        if let Some(title) = &f.title {
-            if title.to_lowercase() == key_lower {
-                return true;
+    let mut items_filled = 0;
+    let mut ids_assigned = 0;
+    if let Some(Value::Array(items)) = doc.get_mut("items") {
+        for item in items.iter_mut().filter_map(Value::as_object_mut) {
+            let mut filled = fill_missing(item, &["guid", "content", "author"], Value::Null);
+            filled |= fill_missing(item, &["read"], Value::Bool(false));
+            items_filled += filled as usize;
+
+            let has_id = item
+                .get("id")
+                .and_then(Value::as_str)
+                .is_some_and(|id| !id.is_empty());
+            if !has_id {
+                let str_field = |name| item.get(name).and_then(Value::as_str).unwrap_or("");
+                // Same fallbacks as `Item::key`
+                let key = match item.get("guid").and_then(Value::as_str) {
+                    Some(guid) => guid,
+                    None if !str_field("link").is_empty() => str_field("link"),
+                    None => str_field("title"),
+                };
+                let url = urls.get(str_field("feed_id")).map_or("", |u| u.as_str());
+                let id = item_id(url, key);
+                item.insert("id".to_string(), Value::String(id));
+                ids_assigned += 1;
             }
         }
+    }
 
-        // Fallback: exact match on id or url
-        if f.id == key || f.url == key {
-            return true;
+    if feeds_filled > 0 {
+        changes.push(format!("fill in missing fields on {feeds_filled} feed(s)"));
+    }
+    if items_filled > 0 {
+        changes.push(format!("fill in missing fields on {items_filled} item(s)"));
+    }
+    if ids_assigned > 0 {
+        changes.push(format!("give ids to {ids_assigned} item(s)"));
+    }
+    changes
+}
+
+/// Version 1 -> 2: an empty webhook delivery log
+fn migrate_v1_to_v2(doc: &mut Map<String, Value>) -> Vec<String> {
+    if fill_missing(doc, &["deliveries"], Value::Array(Vec::new())) {
+        vec!["add an empty delivery log".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Version 2 -> 3: no view recorded yet
+fn migrate_v2_to_v3(doc: &mut Map<String, Value>) -> Vec<String> {
+    if fill_missing(doc, &["last_viewed_at"], Value::Null) {
+        vec!["add last_viewed_at (unset)".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Version 3 -> 4: no feed overrides any settings yet
+fn migrate_v3_to_v4(doc: &mut Map<String, Value>) -> Vec<String> {
+    let none = serde_json::to_value(FeedOverrides::default()).expect("overrides serialize");
+
+    let mut feeds_filled = 0;
+    if let Some(Value::Array(feeds)) = doc.get_mut("feeds") {
+        for feed in feeds.iter_mut().filter_map(Value::as_object_mut) {
+            feeds_filled += fill_missing(feed, &["overrides"], none.clone()) as usize;
         }
+    }
 
-        false
+    if feeds_filled > 0 {
+        vec![format!("add empty overrides to {feeds_filled} feed(s)")]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Version 4 -> 5: no hints recorded until each feed is next fetched
+fn migrate_v4_to_v5(doc: &mut Map<String, Value>) -> Vec<String> {
+    let none = serde_json::to_value(RefreshHints::default()).expect("hints serialize");
+
+    let mut feeds_filled = 0;
+    if let Some(Value::Array(feeds)) = doc.get_mut("feeds") {
+        for feed in feeds.iter_mut().filter_map(Value::as_object_mut) {
+            feeds_filled += fill_missing(feed, &["hints"], none.clone()) as usize;
+        }
+    }
+
+    if feeds_filled > 0 {
+        vec![format!("add empty refresh hints to {feeds_filled} feed(s)")]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Version 5 -> 6: no view before the last one recorded yet
+fn migrate_v5_to_v6(doc: &mut Map<String, Value>) -> Vec<String> {
+    if fill_missing(doc, &["previous_viewed_at"], Value::Null) {
+        vec!["add previous_viewed_at (unset)".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Insert `value` for each of `fields` the object lacks; true if any did
+fn fill_missing(obj: &mut Map<String, Value>, fields: &[&str], value: Value) -> bool {
+    let mut filled = false;
+    for field in fields {
+        if !obj.contains_key(*field) {
+            obj.insert(field.to_string(), value.clone());
+            filled = true;
+        }
+    }
+    filled
+}
+
+/// Restricts commands to a subset of feeds via `--tag` / `--group`
+#[derive(Debug, Default, Clone)]
+pub struct FeedSelector {
+    pub tags: Vec<String>,
+    pub group: Option<String>,
+}
+
+impl FeedSelector {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.group.is_none()
     }
 
-    /// Find feed index by alias/title/id/url
-    pub fn find_feed_index(&self, key: &str) -> Option<usize> {
-        self.feeds.iter().enumerate().find_map(|(i, f)| {
-            if Self::feed_matches(f, key) {
-                Some(i)
-            } else {
-                None
+    /// A feed matches if it carries any of the tags, and sits in the group
+    /// (or one of its subgroups). An empty selector matches every feed.
+    pub fn matches(&self, f: &Feed) -> bool {
+        let tag_ok =
+            self.tags.is_empty() || self.tags.iter().any(|t| f.tags.contains(&normalize_tag(t)));
+
+        let group_ok = match &self.group {
+            None => true,
+            Some(wanted) => {
+                let wanted = wanted.trim_matches('/').to_lowercase();
+                f.group.as_ref().is_some_and(|g| {
+                    let g = g.to_lowercase();
+                    g == wanted || g.starts_with(&format!("{wanted}/"))
+                })
             }
-        })
+        };
+
+        tag_ok && group_ok
+    }
+}
+
+/// Tags are stored trimmed and lowercase so `--tag Rust` finds "rust"
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// Load state from JSON (or create an empty one).
+///
+/// If the file is corrupt (say, from a write cut short before saves were
+/// atomic) and a `state.json.bak` exists, that's a `CorruptStateError`; see
+/// `recover_from_backup`.
+///
+/// Under a shared lock nothing is written, so a file that needs migrating is
+/// a `NeedsExclusiveLock` error instead.
+pub fn load_json_state(path: &Path, mode: LockMode) -> Result<State> {
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        return Ok(State::default());
     }
 
+    let err = match read_state_file(path) {
+        Ok((state, report)) => {
+            if !report.applied.is_empty() {
+                if mode == LockMode::Shared {
+                    return Err(NeedsExclusiveLock.into());
+                }
+                let copy = keep_pre_migration_copy(path, report.from)?;
+                eprintln!(
+                    "Upgraded {} from schema version {} to {} (original kept as {})",
+                    path.display(),
+                    report.from,
+                    SCHEMA_VERSION,
+                    copy.display()
+                );
+            }
+            return Ok(state);
+        }
+        Err(err) if err.is::<NewerSchemaError>() => return Err(err),
+        Err(err) => err,
+    };
+
+    let backup = sibling_path(path, "bak");
+    if !backup.exists() {
+        return Err(err.context(format!("Could not read {}", path.display())));
+    }
+    Err(CorruptStateError {
+        path: path.to_path_buf(),
+        backup,
+        reason: err.to_string(),
+    }
+    .into())
+}
+
+/// Replace a corrupt state file with its `state.json.bak`, keeping the
+/// corrupt file as `state.json.corrupt`. A file that has become readable in
+/// the meantime (another rsso recovered it first) is left alone.
+pub fn recover_from_backup(path: &Path) -> Result<()> {
+    if read_state_file(path).is_ok() {
+        return Ok(());
+    }
+
+    let backup = sibling_path(path, "bak");
+    let (state, _) = read_state_file(&backup)
+        .with_context(|| format!("Backup {} is unreadable too", backup.display()))?;
+    let corrupt = sibling_path(path, "corrupt");
+    fs::rename(path, &corrupt)?;
+    save_json_state(path, &state)?;
+    eprintln!(
+        "Recovered state from {} (corrupt file kept as {})",
+        backup.display(),
+        corrupt.display()
+    );
+    Ok(())
+}
+
+/// Read a state file, migrating it to the current schema in memory
+pub fn read_state_file(path: &Path) -> Result<(State, MigrationReport)> {
+    let contents = fs::read_to_string(path)?;
+    if contents.trim().is_empty() {
+        let report = MigrationReport {
+            from: SCHEMA_VERSION,
+            applied: Vec::new(),
+        };
+        return Ok((State::default(), report));
+    }
+
+    let mut doc: Value = serde_json::from_str(&contents)?;
+    let report = migrate_document(&mut doc)?;
+    let state: State = serde_json::from_value(doc)?;
+    Ok((state, report))
+}
+
+/// Before a migrated state is first saved over the original file, keep the
+/// original as `state.json.v<version>` (once; an existing copy is left alone).
+/// Returns where the copy is.
+pub fn keep_pre_migration_copy(path: &Path, version: u32) -> Result<PathBuf> {
+    let copy = sibling_path(path, &format!("v{version}"));
+    if !copy.exists() {
+        fs::copy(path, &copy)?;
+    }
+    Ok(copy)
+}
+
+/// Ask a yes/no question on the terminal; anything but "y"/"yes" is no
+pub fn confirm(prompt: &str) -> Result<bool> {
+    eprint!("{prompt}");
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// `state.json` -> `state.json.<ext>`, in the same directory
+pub fn sibling_path(path: &Path, ext: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+/// Save state to JSON without ever leaving a half-written file behind.
+///
+/// The new contents go to `state.json.tmp` in the same directory and are
+/// synced to disk before being renamed over `state.json`, so an interrupted
+/// save leaves the previous file intact. The previous file is copied to
+/// `state.json.bak` first.
+pub fn save_json_state(path: &Path, state: &State) -> Result<()> {
+    let json = serde_json::to_string_pretty(state)?;
+
+    if path.exists() {
+        fs::copy(path, sibling_path(path, "bak"))?;
+    }
+
+    write_atomic(path, json.as_bytes())
+}
+
+/// Replace a file's contents via a synced temporary file and a rename, so
+/// readers see either the old contents or the new, never a mix
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+
+    let tmp = sibling_path(path, "tmp");
+    let mut file =
+        File::create(&tmp).with_context(|| format!("Could not write {}", tmp.display()))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp, path)?;
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+impl State {
     /// Add feed (error if duplicate by id or url)
     pub fn add_feed(&mut self, feed: Feed) -> Result<()> {
         if self
@@ -120,12 +767,115 @@ impl State {
         Ok(())
     }
 
+    /// Find an item by id (or an unambiguous id prefix), guid or link
+    pub fn find_item_index(&self, key: &str) -> Result<usize> {
+        if let Some(i) = self
+            .items
+            .iter()
+            .position(|i| i.id == key || i.guid.as_deref() == Some(key) || i.link == key)
+        {
+            return Ok(i);
+        }
+
+        let key_lower = key.to_lowercase();
+        let matches: Vec<usize> = (0..self.items.len())
+            .filter(|&i| !key.is_empty() && self.items[i].id.starts_with(&key_lower))
+            .collect();
+
+        match matches.as_slice() {
+            [] => bail!("No matching item for '{}'", key),
+            [i] => Ok(*i),
+            _ => bail!(
+                "Item id '{}' is ambiguous ({} matches); use more characters",
+                key,
+                matches.len()
+            ),
+        }
+    }
+
+    /// Merge freshly fetched items into a feed's history.
+    ///
+    /// Items already known (matched by `Item::key`) keep their `first_seen_at`
+    /// but take the fetched title, link, dates and summary. Unknown items are
+    /// appended. Returns the items that were new.
+    pub fn merge_items(&mut self, feed_id: &str, fetched: Vec<Item>) -> Vec<Item> {
+        // Existing items for this feed, keyed by identity -> index in self.items.
+        // Items stored before guids were tracked are keyed by link, so they
+        // also get a second entry there to match fetched items by link.
+        let mut known: HashMap<String, usize> = HashMap::new();
+        let mut legacy_by_link: HashMap<String, usize> = HashMap::new();
+        for (idx, item) in self.items.iter().enumerate() {
+            if item.feed_id != feed_id {
+                continue;
+            }
+            known.insert(item.key().to_string(), idx);
+            if item.guid.is_none() && !item.link.is_empty() {
+                legacy_by_link.insert(item.link.clone(), idx);
+            }
+        }
+
+        let mut added = Vec::new();
+        for new in fetched {
+            let existing = known
+                .get(new.key())
+                .or_else(|| legacy_by_link.get(&new.link))
+                .copied();
+
+            match existing {
+                Some(idx) => {
+                    let old = &mut self.items[idx];
+                    old.guid = new.guid;
+                    old.title = new.title;
+                    old.link = new.link;
+                    old.published_at = new.published_at;
+                    old.updated_at = new.updated_at;
+                    old.summary = new.summary;
+                    old.content = new.content;
+                    old.author = new.author;
+                }
+                None => {
+                    known.insert(new.key().to_string(), self.items.len());
+                    added.push(new.clone());
+                    self.items.push(new);
+                }
+            }
+        }
+
+        added
+    }
+
+    /// Keep only the newest `max` items for a feed, leaving other feeds
+    /// untouched. Returns the ids of the items dropped.
+    pub fn trim_history(&mut self, feed_id: &str, max: usize) -> Vec<String> {
+        let mut positions: Vec<usize> = (0..self.items.len())
+            .filter(|&i| self.items[i].feed_id == feed_id)
+            .collect();
+
+        if positions.len() <= max {
+            return Vec::new();
+        }
+
+        // Newest first, then everything past `max` goes
+        positions.sort_by_key(|&i| std::cmp::Reverse(self.items[i].date()));
+        let to_drop: HashSet<usize> = positions.into_iter().skip(max).collect();
+        let dropped = to_drop.iter().map(|&i| self.items[i].id.clone()).collect();
+
+        let mut idx = 0;
+        self.items.retain(|_| {
+            let keep = !to_drop.contains(&idx);
+            idx += 1;
+            keep
+        });
+
+        dropped
+    }
+
     /// Remove a feed & all its items using alias/title/id/url
     pub fn remove_feed(&mut self, key: &str) -> usize {
         let mut removed_ids: Vec<String> = Vec::new();
 
         self.feeds.retain(|f| {
-            let to_remove = Self::feed_matches(f, key);
+            let to_remove = f.matches_key(key);
             if to_remove {
                 removed_ids.push(f.id.clone());
             }
@@ -139,4 +889,265 @@ impl State {
 
         removed_ids.len()
     }
+
+    /// Add a delivery to the log, or replace the entry for the same webhook
+    /// and item
+    pub fn save_delivery(&mut self, delivery: &Delivery) {
+        match self
+            .deliveries
+            .iter_mut()
+            .find(|d| d.webhook == delivery.webhook && d.item_id == delivery.item_id)
+        {
+            Some(d) => *d = delivery.clone(),
+            None => self.deliveries.push(delivery.clone()),
+        }
+    }
+
+    /// Keep only the newest `max` entries of the delivery log
+    pub fn trim_deliveries(&mut self, max: usize) {
+        let excess = self.deliveries.len().saturating_sub(max);
+        self.deliveries.drain(..excess);
+    }
+}
+
+/// A scratch directory for a test: emptied when it's made, and removed
+/// with everything in it when dropped
+#[cfg(test)]
+pub struct TestDir(PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    /// `rsso-<name>-test-<pid>` in the system temp directory
+    pub fn new(name: &str) -> TestDir {
+        let path = std::env::temp_dir().join(format!("rsso-{name}-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir(path)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEED_URL: &str = "https://blog.rust-lang.org/feed.xml";
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    /// Migrate a fixture, checking which version it started at
+    fn migrate_fixture(json: &str, version: u32) -> State {
+        let mut doc: Value = serde_json::from_str(json).unwrap();
+        let report = migrate_document(&mut doc).unwrap();
+        assert_eq!(report.from, version);
+        assert_eq!(report.applied.len() as u32, SCHEMA_VERSION - version);
+        assert_eq!(doc["schema_version"], SCHEMA_VERSION);
+        serde_json::from_value(doc).unwrap()
+    }
+
+    /// What every fixture holds, from the first release on
+    fn assert_base_data(state: &State) {
+        assert_eq!(state.schema_version, SCHEMA_VERSION);
+        assert_eq!(state.feeds.len(), 1);
+        let feed = &state.feeds[0];
+        assert_eq!(feed.id, "rust");
+        assert_eq!(feed.url, FEED_URL);
+        assert_eq!(feed.alias.as_deref(), Some("rust"));
+        assert_eq!(feed.title.as_deref(), Some("Rust Blog"));
+        assert_eq!(feed.added_at, at("2024-01-01T09:00:00Z"));
+        assert_eq!(feed.last_fetched_at, Some(at("2024-03-21T12:00:00Z")));
+        assert_eq!(feed.last_error, None);
+        assert_eq!(feed.hints, RefreshHints::default());
+
+        let links = [
+            "https://blog.rust-lang.org/2024/02/08/Rust-1.76.0.html",
+            "https://blog.rust-lang.org/2024/03/21/Rust-1.77.0.html",
+        ];
+        assert_eq!(state.items.len(), 2);
+        for (item, link) in state.items.iter().zip(links) {
+            assert_eq!(item.id, item_id(FEED_URL, link));
+            assert_eq!(item.feed_id, "rust");
+            assert_eq!(item.link, link);
+        }
+
+        let [first, second] = &state.items[..] else {
+            unreachable!()
+        };
+        assert_eq!(first.title, "Announcing Rust 1.76.0");
+        assert_eq!(first.published_at, Some(at("2024-02-08T00:00:00Z")));
+        assert_eq!(first.updated_at, None);
+        assert_eq!(first.summary.as_deref(), Some("Rust 1.76.0 is out"));
+        assert_eq!(first.first_seen_at, at("2024-02-08T10:00:00Z"));
+        assert_eq!(second.title, "Announcing Rust 1.77.0");
+        assert_eq!(second.published_at, Some(at("2024-03-21T00:00:00Z")));
+        assert_eq!(second.updated_at, Some(at("2024-03-22T00:00:00Z")));
+        assert_eq!(second.summary, None);
+        assert_eq!(second.first_seen_at, at("2024-03-21T10:00:00Z"));
+    }
+
+    /// Fields added in version 1
+    fn assert_v1_data(state: &State) {
+        let feed = &state.feeds[0];
+        assert_eq!(feed.group.as_deref(), Some("lang"));
+        assert_eq!(feed.tags, BTreeSet::from(["rust".to_string()]));
+        assert_eq!(feed.etag.as_deref(), Some("\"5f2a\""));
+        assert_eq!(
+            feed.last_modified.as_deref(),
+            Some("Thu, 21 Mar 2024 10:00:00 GMT")
+        );
+
+        let [first, second] = &state.items[..] else {
+            unreachable!()
+        };
+        assert_eq!(first.guid.as_deref(), Some(first.link.as_str()));
+        assert_eq!(first.content.as_deref(), Some("<p>Rust 1.76.0 is out</p>"));
+        assert_eq!(first.author.as_deref(), Some("The Release Team"));
+        assert!(first.read);
+        assert_eq!(second.guid.as_deref(), Some(second.link.as_str()));
+        assert_eq!(second.content, None);
+        assert_eq!(second.author, None);
+        assert!(!second.read);
+    }
+
+    /// The delivery log added in version 2
+    fn assert_v2_data(state: &State) {
+        assert_eq!(
+            state.deliveries,
+            vec![Delivery {
+                webhook: "http://localhost:9000/rsso".to_string(),
+                item_id: state.items[1].id.clone(),
+                item_title: "Announcing Rust 1.77.0".to_string(),
+                created_at: at("2024-03-21T12:00:00Z"),
+                attempts: 2,
+                last_attempt_at: Some(at("2024-03-21T12:05:00Z")),
+                delivered_at: Some(at("2024-03-21T12:05:00Z")),
+                last_error: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn migrates_v0() {
+        let state = migrate_fixture(include_str!("../tests/fixtures/state-v0.json"), 0);
+        assert_base_data(&state);
+
+        let feed = &state.feeds[0];
+        assert_eq!(feed.group, None);
+        assert!(feed.tags.is_empty());
+        assert_eq!(feed.etag, None);
+        assert_eq!(feed.overrides, FeedOverrides::default());
+        for item in &state.items {
+            assert_eq!(item.guid, None);
+            assert_eq!(item.content, None);
+            assert_eq!(item.author, None);
+            assert!(!item.read);
+        }
+        assert!(state.deliveries.is_empty());
+        assert_eq!(state.last_viewed_at, None);
+    }
+
+    #[test]
+    fn migrates_v1() {
+        let state = migrate_fixture(include_str!("../tests/fixtures/state-v1.json"), 1);
+        assert_base_data(&state);
+        assert_v1_data(&state);
+        assert!(state.deliveries.is_empty());
+        assert_eq!(state.last_viewed_at, None);
+        assert_eq!(state.feeds[0].overrides, FeedOverrides::default());
+    }
+
+    #[test]
+    fn migrates_v2() {
+        let state = migrate_fixture(include_str!("../tests/fixtures/state-v2.json"), 2);
+        assert_base_data(&state);
+        assert_v1_data(&state);
+        assert_v2_data(&state);
+        assert_eq!(state.last_viewed_at, None);
+        assert_eq!(state.feeds[0].overrides, FeedOverrides::default());
+    }
+
+    #[test]
+    fn migrates_v3() {
+        let state = migrate_fixture(include_str!("../tests/fixtures/state-v3.json"), 3);
+        assert_base_data(&state);
+        assert_v1_data(&state);
+        assert_v2_data(&state);
+        assert_eq!(state.last_viewed_at, Some(at("2024-03-22T08:00:00Z")));
+        assert_eq!(state.feeds[0].overrides, FeedOverrides::default());
+    }
+
+    #[test]
+    fn migrates_v4() {
+        let state = migrate_fixture(include_str!("../tests/fixtures/state-v4.json"), 4);
+        assert_base_data(&state);
+        assert_v1_data(&state);
+        assert_v2_data(&state);
+        assert_eq!(state.last_viewed_at, Some(at("2024-03-22T08:00:00Z")));
+        assert_eq!(
+            state.feeds[0].overrides,
+            FeedOverrides {
+                refresh_mins: Some(120),
+                max_history: None,
+                limit: Some(5),
+                label: Some("Rust".to_string()),
+                disabled: false,
+            }
+        );
+        assert_eq!(state.previous_viewed_at, None);
+    }
+
+    #[test]
+    fn migrates_v5() {
+        let state = migrate_fixture(include_str!("../tests/fixtures/state-v5.json"), 5);
+        assert_base_data(&state);
+        assert_v1_data(&state);
+        assert_v2_data(&state);
+        assert_eq!(state.last_viewed_at, Some(at("2024-03-22T08:00:00Z")));
+        assert_eq!(state.feeds[0].overrides.limit, Some(5));
+        assert_eq!(state.previous_viewed_at, None);
+    }
+
+    #[test]
+    fn newer_schema_is_refused() {
+        let mut doc = serde_json::json!({ "schema_version": SCHEMA_VERSION + 1 });
+        let err = migrate_document(&mut doc).unwrap_err();
+        assert!(err.is::<NewerSchemaError>());
+    }
+
+    #[test]
+    fn shared_lock_loads_never_write() {
+        let dir = TestDir::new("state");
+        let path = dir.join("state.json");
+
+        // An old file isn't upgraded in place
+        fs::write(&path, include_str!("../tests/fixtures/state-v0.json")).unwrap();
+        let err = load_json_state(&path, LockMode::Shared).unwrap_err();
+        assert!(err.is::<NeedsExclusiveLock>());
+        assert!(!sibling_path(&path, "v0").exists());
+
+        // Nor is a corrupt one recovered from its backup
+        fs::copy(&path, sibling_path(&path, "bak")).unwrap();
+        fs::write(&path, "{ not json").unwrap();
+        let err = load_json_state(&path, LockMode::Shared).unwrap_err();
+        assert!(err.is::<CorruptStateError>());
+        assert!(!sibling_path(&path, "corrupt").exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{ not json");
+    }
 }
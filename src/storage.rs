@@ -0,0 +1,506 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, StorageKind};
+use crate::lock::{self, LockMode, StoreLock};
+use crate::search::{SearchIndex, SearchQuery};
+use crate::sqlite::SqliteStore;
+use crate::state::{
+    self, CorruptStateError, Delivery, Feed, FeedSelector, Item, NeedsExclusiveLock, State,
+};
+
+/// Which items to return from `Storage::items`
+#[derive(Debug, Default, Clone)]
+pub struct ItemQuery {
+    /// Only items from these feeds (`None` for every feed)
+    pub feed_ids: Option<Vec<String>>,
+    pub unread_only: bool,
+    /// Only items dated at or after this
+    pub since: Option<DateTime<Utc>>,
+    /// Only items dated before this
+    pub until: Option<DateTime<Utc>>,
+    /// Only items first seen after this
+    pub seen_after: Option<DateTime<Utc>>,
+    /// Return at most this many items
+    pub limit: Option<usize>,
+    /// Skip this many of the items first, to page through them
+    pub offset: usize,
+}
+
+impl ItemQuery {
+    pub fn matches(&self, item: &Item) -> bool {
+        let feed_ok = self
+            .feed_ids
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&item.feed_id));
+        let date = item.date();
+        feed_ok
+            && (!self.unread_only || !item.read)
+            && self.since.is_none_or(|s| date >= s)
+            && self.until.is_none_or(|u| date < u)
+            && self.seen_after.is_none_or(|s| item.first_seen_at > s)
+    }
+}
+
+/// Where feeds and items live between runs.
+///
+/// Commands only talk to this trait, so the same logic runs against the JSON
+/// file, SQLite, or a `MemoryStore` that never touches the filesystem.
+/// Implementations may write changes as they happen or hold them until
+/// `save`.
+pub trait Storage {
+    /// Every subscribed feed, in the order they were added
+    fn feeds(&self) -> &[Feed];
+
+    /// Add a feed (error if one with the same id or url exists)
+    fn add_feed(&mut self, feed: Feed) -> Result<()>;
+
+    /// Replace the stored feed with the same url. If its id changed, its
+    /// items move with it.
+    fn update_feed(&mut self, feed: &Feed) -> Result<()>;
+
+    /// Remove feeds matching alias/title/id/url and all their items,
+    /// returning how many feeds went
+    fn remove_feed(&mut self, key: &str) -> Result<usize>;
+
+    /// Items matching the query, newest first
+    fn items(&self, query: &ItemQuery) -> Result<Vec<Item>>;
+
+    /// Find an item by id (or an unambiguous id prefix), guid or link
+    fn find_item(&self, key: &str) -> Result<Item>;
+
+    /// Items containing every search term and passing the query's filters,
+    /// best match first
+    fn search(&self, query: &SearchQuery) -> Result<Vec<Item>>;
+
+    /// Merge freshly fetched items into a feed's history (see
+    /// `State::merge_items`), returning the items that were new
+    fn upsert_items(&mut self, feed_id: &str, items: Vec<Item>) -> Result<Vec<Item>>;
+
+    /// Mark items read by id, returning how many were unread before
+    fn mark_read(&mut self, ids: &[String]) -> Result<usize>;
+
+    /// Keep only the newest `max` items for a feed
+    fn trim_history(&mut self, feed_id: &str, max: usize) -> Result<()>;
+
+    /// The webhook delivery log, oldest first
+    fn deliveries(&self) -> Result<Vec<Delivery>>;
+
+    /// Add a delivery to the log, or update the entry for the same webhook
+    /// and item
+    fn save_delivery(&mut self, delivery: &Delivery) -> Result<()>;
+
+    /// Keep only the newest `max` entries of the delivery log
+    fn trim_deliveries(&mut self, max: usize) -> Result<()>;
+
+    /// When items were last listed on a terminal
+    fn last_viewed_at(&self) -> Result<Option<DateTime<Utc>>>;
+
+    /// The `last_viewed_at` before the current one
+    fn previous_viewed_at(&self) -> Result<Option<DateTime<Utc>>>;
+
+    /// Record a view, keeping the one it replaces as `previous_viewed_at`
+    fn set_last_viewed_at(&mut self, at: DateTime<Utc>) -> Result<()>;
+
+    /// Persist any changes not written yet
+    fn save(&mut self) -> Result<()>;
+
+    /// Find a feed by alias/title/id/url
+    fn find_feed(&self, key: &str) -> Option<Feed> {
+        self.feeds().iter().find(|f| f.matches_key(key)).cloned()
+    }
+
+    /// Find a feed by alias/title/id/url among feeds matching the selector
+    fn find_selected_feed(&self, key: &str, selector: &FeedSelector) -> Option<Feed> {
+        self.feeds()
+            .iter()
+            .find(|f| selector.matches(f) && f.matches_key(key))
+            .cloned()
+    }
+
+    /// All feeds matching the selector
+    fn select_feeds(&self, selector: &FeedSelector) -> Vec<Feed> {
+        self.feeds()
+            .iter()
+            .filter(|f| selector.matches(f))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Open the storage backend chosen in config.toml, under a lock taken with
+/// `mode` (see `lock_and_open`)
+fn open(cfg: &Config, mode: LockMode) -> Result<Box<dyn Storage>> {
+    Ok(match cfg.storage {
+        StorageKind::Json => Box::new(JsonStore::open(&cfg.state_path, mode)?),
+        StorageKind::Sqlite => Box::new(SqliteStore::open(cfg, mode)?),
+    })
+}
+
+/// Lock the configured store with `mode` and open it.
+///
+/// A store that needs upgrading first is opened under an exclusive lock
+/// instead, and saved straight away so read-only commands don't upgrade it
+/// on every run. A corrupt state.json is only recovered from its backup if
+/// the user agrees, which is asked with no lock held, so an unanswered prompt
+/// doesn't hold up other rsso processes.
+pub fn lock_and_open(cfg: &Config, mode: LockMode) -> Result<(StoreLock, Box<dyn Storage>)> {
+    let mut mode_used = mode;
+    loop {
+        let lock = lock::lock_store(cfg, mode_used)?;
+        let err = match open(cfg, mode_used) {
+            Ok(mut store) => {
+                if mode_used != mode {
+                    store.save()?;
+                }
+                return Ok((lock, store));
+            }
+            Err(err) => err,
+        };
+        drop(lock);
+
+        if err.is::<NeedsExclusiveLock>() {
+            mode_used = LockMode::Exclusive;
+            continue;
+        }
+        match err.downcast::<CorruptStateError>() {
+            Ok(corrupt) if corrupt.ask_to_recover()? => {
+                let _lock = lock::lock_store(cfg, LockMode::Exclusive)?;
+                state::recover_from_backup(&corrupt.path)?;
+            }
+            Ok(corrupt) => return Err(corrupt.into()),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Keeps everything in a `State` in memory; nothing is persisted.
+///
+/// A search index over the items is kept up to date as they change.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    state: State,
+    index: SearchIndex,
+    /// Whether the index changed since it was built or loaded
+    index_changed: bool,
+    /// Item id -> where the item is in `state.items`, for search hits
+    positions: HashMap<String, usize>,
+}
+
+impl MemoryStore {
+    pub fn from_state(state: State) -> MemoryStore {
+        let index = SearchIndex::build(&state.items);
+        MemoryStore::new(state, index, true)
+    }
+
+    /// Use a previously built index, if it still matches the items
+    pub fn with_index(state: State, index: SearchIndex) -> MemoryStore {
+        if index.covers(&state.items) {
+            MemoryStore::new(state, index, false)
+        } else {
+            MemoryStore::from_state(state)
+        }
+    }
+
+    fn new(state: State, index: SearchIndex, index_changed: bool) -> MemoryStore {
+        let mut store = MemoryStore {
+            state,
+            index,
+            index_changed,
+            positions: HashMap::new(),
+        };
+        store.find_positions();
+        store
+    }
+
+    /// Note where each item is, after items were added or removed
+    fn find_positions(&mut self) {
+        self.positions = self
+            .state
+            .items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (item.id.clone(), i))
+            .collect();
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// The search index, stamped with the items it's to be saved with, if
+    /// it changed since it was last taken
+    pub fn changed_index(&mut self) -> Option<&SearchIndex> {
+        if !std::mem::take(&mut self.index_changed) {
+            return None;
+        }
+        self.index.stamp(&self.state.items);
+        Some(&self.index)
+    }
+}
+
+impl Storage for MemoryStore {
+    fn feeds(&self) -> &[Feed] {
+        &self.state.feeds
+    }
+
+    fn add_feed(&mut self, feed: Feed) -> Result<()> {
+        self.state.add_feed(feed)
+    }
+
+    fn update_feed(&mut self, feed: &Feed) -> Result<()> {
+        let Some(stored) = self.state.feeds.iter_mut().find(|f| f.url == feed.url) else {
+            anyhow::bail!("No feed with url {}", feed.url);
+        };
+
+        if stored.id != feed.id {
+            for item in self.state.items.iter_mut() {
+                if item.feed_id == stored.id {
+                    item.feed_id = feed.id.clone();
+                }
+            }
+        }
+        *stored = feed.clone();
+        Ok(())
+    }
+
+    fn remove_feed(&mut self, key: &str) -> Result<usize> {
+        let feed_ids: Vec<&String> = self
+            .state
+            .feeds
+            .iter()
+            .filter(|f| f.matches_key(key))
+            .map(|f| &f.id)
+            .collect();
+        for item in self
+            .state
+            .items
+            .iter()
+            .filter(|i| feed_ids.contains(&&i.feed_id))
+        {
+            self.index.remove(&item.id);
+            self.index_changed = true;
+        }
+
+        let removed = self.state.remove_feed(key);
+        self.find_positions();
+        Ok(removed)
+    }
+
+    fn items(&self, query: &ItemQuery) -> Result<Vec<Item>> {
+        let mut items: Vec<&Item> = self
+            .state
+            .items
+            .iter()
+            .filter(|i| query.matches(i))
+            .collect();
+        items.sort_by_key(|i| std::cmp::Reverse(i.date()));
+        Ok(items
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    fn find_item(&self, key: &str) -> Result<Item> {
+        let idx = self.state.find_item_index(key)?;
+        Ok(self.state.items[idx].clone())
+    }
+
+    fn search(&self, query: &SearchQuery) -> Result<Vec<Item>> {
+        let hits = self
+            .index
+            .search(&query.terms)
+            .into_iter()
+            .filter_map(|id| self.positions.get(&id).map(|&i| &self.state.items[i]))
+            .filter(|i| query.matches(i))
+            .skip(query.offset)
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+        Ok(hits)
+    }
+
+    fn upsert_items(&mut self, feed_id: &str, items: Vec<Item>) -> Result<Vec<Item>> {
+        let keys: HashSet<String> = items.iter().map(|i| i.key().to_string()).collect();
+        let added = self.state.merge_items(feed_id, items);
+
+        // Re-index everything that was fetched, since known items take the
+        // fetched text too
+        for item in self
+            .state
+            .items
+            .iter()
+            .filter(|i| i.feed_id == feed_id && keys.contains(i.key()))
+        {
+            self.index.add(item);
+            self.index_changed = true;
+        }
+
+        self.find_positions();
+        Ok(added)
+    }
+
+    fn mark_read(&mut self, ids: &[String]) -> Result<usize> {
+        let ids: HashSet<&String> = ids.iter().collect();
+        let mut count = 0;
+        for item in self.state.items.iter_mut() {
+            if !item.read && ids.contains(&item.id) {
+                item.read = true;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    fn trim_history(&mut self, feed_id: &str, max: usize) -> Result<()> {
+        for id in self.state.trim_history(feed_id, max) {
+            self.index.remove(&id);
+            self.index_changed = true;
+        }
+        self.find_positions();
+        Ok(())
+    }
+
+    fn deliveries(&self) -> Result<Vec<Delivery>> {
+        Ok(self.state.deliveries.clone())
+    }
+
+    fn save_delivery(&mut self, delivery: &Delivery) -> Result<()> {
+        self.state.save_delivery(delivery);
+        Ok(())
+    }
+
+    fn trim_deliveries(&mut self, max: usize) -> Result<()> {
+        self.state.trim_deliveries(max);
+        Ok(())
+    }
+
+    fn last_viewed_at(&self) -> Result<Option<DateTime<Utc>>> {
+        Ok(self.state.last_viewed_at)
+    }
+
+    fn previous_viewed_at(&self) -> Result<Option<DateTime<Utc>>> {
+        Ok(self.state.previous_viewed_at)
+    }
+
+    fn set_last_viewed_at(&mut self, at: DateTime<Utc>) -> Result<()> {
+        self.state.previous_viewed_at = self.state.last_viewed_at.replace(at);
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The state.json file: loaded into memory on open, rewritten on `save`.
+///
+/// The search index is kept next to it in `state.json.index`, and rebuilt
+/// if that file is missing, unreadable or out of step with the items.
+#[derive(Debug)]
+pub struct JsonStore {
+    path: PathBuf,
+    mem: MemoryStore,
+}
+
+impl JsonStore {
+    pub fn open(path: &Path, mode: LockMode) -> Result<JsonStore> {
+        let state = state::load_json_state(path, mode)?;
+
+        let index = fs::read(index_path(path))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<SearchIndex>(&bytes).ok());
+        let mem = match index {
+            Some(index) => MemoryStore::with_index(state, index),
+            None => MemoryStore::from_state(state),
+        };
+
+        Ok(JsonStore {
+            path: path.to_path_buf(),
+            mem,
+        })
+    }
+}
+
+fn index_path(state_path: &Path) -> PathBuf {
+    state::sibling_path(state_path, "index")
+}
+
+impl Storage for JsonStore {
+    fn feeds(&self) -> &[Feed] {
+        self.mem.feeds()
+    }
+
+    fn add_feed(&mut self, feed: Feed) -> Result<()> {
+        self.mem.add_feed(feed)
+    }
+
+    fn update_feed(&mut self, feed: &Feed) -> Result<()> {
+        self.mem.update_feed(feed)
+    }
+
+    fn remove_feed(&mut self, key: &str) -> Result<usize> {
+        self.mem.remove_feed(key)
+    }
+
+    fn items(&self, query: &ItemQuery) -> Result<Vec<Item>> {
+        self.mem.items(query)
+    }
+
+    fn find_item(&self, key: &str) -> Result<Item> {
+        self.mem.find_item(key)
+    }
+
+    fn search(&self, query: &SearchQuery) -> Result<Vec<Item>> {
+        self.mem.search(query)
+    }
+
+    fn upsert_items(&mut self, feed_id: &str, items: Vec<Item>) -> Result<Vec<Item>> {
+        self.mem.upsert_items(feed_id, items)
+    }
+
+    fn mark_read(&mut self, ids: &[String]) -> Result<usize> {
+        self.mem.mark_read(ids)
+    }
+
+    fn trim_history(&mut self, feed_id: &str, max: usize) -> Result<()> {
+        self.mem.trim_history(feed_id, max)
+    }
+
+    fn deliveries(&self) -> Result<Vec<Delivery>> {
+        self.mem.deliveries()
+    }
+
+    fn save_delivery(&mut self, delivery: &Delivery) -> Result<()> {
+        self.mem.save_delivery(delivery)
+    }
+
+    fn trim_deliveries(&mut self, max: usize) -> Result<()> {
+        self.mem.trim_deliveries(max)
+    }
+
+    fn last_viewed_at(&self) -> Result<Option<DateTime<Utc>>> {
+        self.mem.last_viewed_at()
+    }
+
+    fn previous_viewed_at(&self) -> Result<Option<DateTime<Utc>>> {
+        self.mem.previous_viewed_at()
+    }
+
+    fn set_last_viewed_at(&mut self, at: DateTime<Utc>) -> Result<()> {
+        self.mem.set_last_viewed_at(at)
+    }
+
+    fn save(&mut self) -> Result<()> {
+        state::save_json_state(&self.path, self.mem.state())?;
+        if let Some(index) = self.mem.changed_index() {
+            state::write_atomic(&index_path(&self.path), &serde_json::to_vec(index)?)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+use anyhow::{Result, bail};
+use chrono::format::{Item as FmtItem, StrftimeItems};
+use colored::Colorize;
+
+use crate::state::Item;
+
+/// Template used when neither config.toml nor `--template` sets one
+pub const DEFAULT_TEMPLATE: &str = "{id} | {date:%d %b %y} | {feed} | {title} | {link}";
+
+/// A parsed item line template, e.g. "{date:%Y-%m-%d} {feed}: {title}".
+///
+/// Placeholders:
+/// - `{id}`: short item id
+/// - `{date}` / `{date:<strftime>}`: best known date (default `%d %b %y`)
+/// - `{feed}`: feed label (alias, title or id)
+/// - `{title}`, `{link}`, `{author}`
+/// - `{summary}` / `{summary:<n>}`: summary as plain text, cut to n characters
+///
+/// Use `{{` and `}}` for literal braces.
+#[derive(Debug, Clone)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Id,
+    Date(String),
+    Feed,
+    Title,
+    Link,
+    Author,
+    Summary(Option<usize>),
+}
+
+impl Template {
+    /// Parse and validate a template string
+    pub fn parse(src: &str) -> Result<Template> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = src.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => bail!("Unclosed '{{' in template \"{}\"", src),
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(parse_placeholder(&placeholder)?);
+                }
+                '}' => bail!("Unmatched '}}' in template \"{}\" (use '}}}}')", src),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template { segments })
+    }
+
+    /// Render one item as a single line, with its title picked out in yellow
+    /// if `highlight` is set
+    pub fn render(&self, item: &Item, feed_label: &str, highlight: bool) -> String {
+        let mut out = String::new();
+
+        for seg in &self.segments {
+            let value = seg.value(item, feed_label);
+            match seg {
+                Segment::Literal(s) => out.push_str(s),
+                Segment::Id => out.push_str(&value.dimmed().to_string()),
+                Segment::Title if highlight => out.push_str(&value.bold().yellow().to_string()),
+                Segment::Title => out.push_str(&value.bold().to_string()),
+                Segment::Link => out.push_str(&value.blue().to_string()),
+                _ => out.push_str(&value),
+            }
+        }
+
+        out
+    }
+
+    /// Render without colours, passing each placeholder's value through
+    /// `escape` (to embed it in JSON, say). Literal text is left as is.
+    pub fn render_plain(
+        &self,
+        item: &Item,
+        feed_label: &str,
+        escape: impl Fn(&str) -> String,
+    ) -> String {
+        let mut out = String::new();
+
+        for seg in &self.segments {
+            match seg {
+                Segment::Literal(s) => out.push_str(s),
+                _ => out.push_str(&escape(&seg.value(item, feed_label))),
+            }
+        }
+
+        out
+    }
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Template::parse(DEFAULT_TEMPLATE).expect("default template is valid")
+    }
+}
+
+impl Segment {
+    /// The plain text a segment stands for
+    fn value(&self, item: &Item, feed_label: &str) -> String {
+        match self {
+            Segment::Literal(s) => s.clone(),
+            Segment::Id => item.short_id().to_string(),
+            Segment::Date(fmt) => item.date().format(fmt).to_string(),
+            Segment::Feed => feed_label.to_string(),
+            Segment::Title => item.title.clone(),
+            Segment::Link => item.link.clone(),
+            Segment::Author => item.author.clone().unwrap_or_default(),
+            Segment::Summary(max) => {
+                let text = item
+                    .summary
+                    .as_deref()
+                    .map(html_to_line)
+                    .unwrap_or_default();
+                truncate(&text, *max)
+            }
+        }
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Segment> {
+    let (name, arg) = match placeholder.split_once(':') {
+        Some((name, arg)) => (name.trim(), Some(arg)),
+        None => (placeholder.trim(), None),
+    };
+
+    let no_arg = |seg: Segment| match arg {
+        Some(_) => bail!("Placeholder {{{}}} doesn't take an argument", name),
+        None => Ok(seg),
+    };
+
+    match name {
+        "id" => no_arg(Segment::Id),
+        "feed" => no_arg(Segment::Feed),
+        "title" => no_arg(Segment::Title),
+        "link" => no_arg(Segment::Link),
+        "author" => no_arg(Segment::Author),
+        "date" => {
+            let fmt = arg.unwrap_or("%d %b %y");
+            if StrftimeItems::new(fmt).any(|i| matches!(i, FmtItem::Error)) {
+                bail!("Invalid date format '{}' in {{date:...}}", fmt);
+            }
+            Ok(Segment::Date(fmt.to_string()))
+        }
+        "summary" => match arg {
+            None => Ok(Segment::Summary(None)),
+            Some(n) => match n.trim().parse::<usize>() {
+                Ok(n) => Ok(Segment::Summary(Some(n))),
+                Err(_) => bail!("Expected a length in {{summary:<n>}}, got '{}'", n),
+            },
+        },
+        other => bail!(
+            "Unknown placeholder {{{}}} (expected id, date, feed, title, link, summary or author)",
+            other
+        ),
+    }
+}
+
+/// Flatten an HTML fragment to one line of plain text
+pub fn html_to_line(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Cut text to at most `max` characters, marking the cut with an ellipsis
+fn truncate(text: &str, max: Option<usize>) -> String {
+    match max {
+        Some(max) if text.chars().count() > max => {
+            let cut: String = text.chars().take(max.saturating_sub(1)).collect();
+            format!("{}…", cut.trim_end())
+        }
+        _ => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    fn item() -> Item {
+        let at = DateTime::parse_from_rfc3339("2024-03-21T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        Item {
+            id: "3f9c02ab11223344".to_string(),
+            feed_id: "rust".to_string(),
+            guid: None,
+            title: "Announcing Rust 1.77.0".to_string(),
+            link: "https://blog.rust-lang.org/2024/03/21/Rust-1.77.0.html".to_string(),
+            published_at: Some(at),
+            updated_at: None,
+            summary: Some("<p>Rust 1.77.0 is <em>out</em> &amp; ready</p>".to_string()),
+            content: None,
+            author: None,
+            first_seen_at: at,
+            read: false,
+        }
+    }
+
+    fn render(src: &str) -> String {
+        Template::parse(src)
+            .unwrap()
+            .render_plain(&item(), "Rust", |v| v.to_string())
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        assert_eq!(
+            render("{id} {date:%Y-%m-%d} {feed}: {title} <{link}> by {author}"),
+            "3f9c02ab 2024-03-21 Rust: Announcing Rust 1.77.0 \
+             <https://blog.rust-lang.org/2024/03/21/Rust-1.77.0.html> by "
+        );
+        assert_eq!(render("{date}"), "21 Mar 24");
+        assert_eq!(render("{ title }"), "Announcing Rust 1.77.0");
+        assert_eq!(render("{{{feed}}}"), "{Rust}");
+    }
+
+    #[test]
+    fn summaries_are_flattened_and_cut() {
+        assert_eq!(render("{summary}"), "Rust 1.77.0 is out & ready");
+        assert_eq!(render("{summary:10}"), "Rust 1.77…");
+        assert_eq!(render("{summary:100}"), "Rust 1.77.0 is out & ready");
+    }
+
+    #[test]
+    fn bad_templates_are_refused() {
+        for src in [
+            "{name}",
+            "{title:20}",
+            "{summary:many}",
+            "{date:%Q}",
+            "{title",
+            "title}",
+        ] {
+            assert!(Template::parse(src).is_err(), "{src}");
+        }
+    }
+
+    #[test]
+    fn placeholder_values_are_escaped_but_literals_are_not() {
+        let template = Template::parse("\"{title}\" {feed}").unwrap();
+        let out = template.render_plain(&item(), "A \"B\"", |v| v.replace('"', "\\\""));
+        assert_eq!(out, "\"Announcing Rust 1.77.0\" A \\\"B\\\"");
+    }
+}
@@ -0,0 +1,68 @@
+use reqwest::Client;
+use reqwest::header::CONTENT_TYPE;
+use std::time::Duration;
+
+use crate::config::Webhook;
+use crate::fetch::FetchError;
+
+/// Requests made per delivery in each `rsso watch` cycle, with a growing
+/// delay between them
+pub const WATCH_TRIES: u32 = 3;
+
+/// Requests made per delivery by other commands, which don't wait to retry
+/// and leave failures to later runs
+pub const INLINE_TRIES: u32 = 1;
+
+/// Requests made in total before a delivery is given up on
+pub const MAX_ATTEMPTS: u32 = 9;
+
+/// `last_error` of a delivery a run has taken on, but not finished yet
+pub const IN_FLIGHT: &str = "Being delivered";
+
+/// How long a delivery taken on by a run is left alone for, after which that
+/// run is assumed to have died and the delivery is tried again
+pub const IN_FLIGHT_MINS: i64 = 5;
+
+/// How many entries the delivery log keeps
+pub const MAX_LOG_ENTRIES: usize = 1000;
+
+/// Result of trying to deliver one body
+pub struct PostOutcome {
+    /// Requests made
+    pub tries: u32,
+    /// Why the last request failed, if none succeeded
+    pub error: Option<String>,
+}
+
+/// POST a body to a webhook, retrying with a growing delay on failure.
+///
+/// Makes up to `max_tries` requests (at least one). Any 2xx response counts
+/// as delivered.
+pub async fn post(client: &Client, hook: &Webhook, body: &str, max_tries: u32) -> PostOutcome {
+    let mut tries = 0;
+    let mut delay = Duration::from_secs(1);
+
+    loop {
+        tries += 1;
+        let error = match client
+            .post(&hook.url)
+            .header(CONTENT_TYPE, &hook.content_type)
+            .body(body.to_string())
+            .send()
+            .await
+        {
+            Ok(resp) if resp.status().is_success() => return PostOutcome { tries, error: None },
+            Ok(resp) => FetchError::Status(resp.status()).to_string(),
+            Err(err) => FetchError::from(err).to_string(),
+        };
+
+        if tries >= max_tries {
+            return PostOutcome {
+                tries,
+                error: Some(error),
+            };
+        }
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+}